@@ -15,4 +15,17 @@ pub struct LoginData {
 pub struct VerificationRequest {
     pub email: String,
     pub code: String,
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PasswordResetRequest {
+    pub email: String,
+    pub code: String,
+    /// The new password, in the same form as for logging in.
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
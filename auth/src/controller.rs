@@ -1,43 +1,86 @@
 use crate::sql_operations;
-use crate::auth::{LoginData, VerificationRequest};
-use actix_web::{web, HttpResponse, Responder};
-use log::error;
-use crate::email_operations::{generate_verification_code, send_verification_email};
-use crate::email_operations::VERIFICATION_CODES;
-use auth::generate_jwt;
+use crate::auth::{LoginData, PasswordResetRequest, RefreshRequest, VerificationRequest};
+use actix_web::{web, HttpRequest, HttpResponse};
+use data_access::AppError;
+use crate::email_operations::{
+    generate_verification_code, send_password_reset_email, send_verification_email,
+};
+use crate::sql_operations::CodePurpose;
+use auth::email::EmailSender;
+use auth::session;
+use auth::{AuthenticatedUser, Claims, Role};
 use actix_web::post;
+use std::net::IpAddr;
 
-/// Logs in a user and returns a JWT token if successful.
+/// Logs in a user and returns a JWT token and a refresh token if successful.
 #[utoipa::path(
     request_body = LoginData,
     responses(
         (status = 200, description = "User logged in successfully.", body = User, headers(
-            ("x-token" = String, description = "JWT token for authenticated requests.")
+            ("x-token" = String, description = "JWT token for authenticated requests."),
+            ("x-refresh-token" = String, description = "Refresh token for /token/refresh.")
         )),
         (status = 401, description = "Unauthorized. Invalid credentials."),
+        (status = 403, description = "Email address has not been verified."),
         (status = 500, description = "Internal server error.")
     )
 )]
 #[post("/login")]
-pub async fn login_user(login_data: web::Json<LoginData>) -> impl Responder {
+pub async fn login_user(login_data: web::Json<LoginData>) -> Result<HttpResponse, AppError> {
     let email = login_data.email.clone();
     let password = login_data.password.clone();
 
-    match sql_operations::login(email, password).await {
-        Some(user) => match generate_jwt(user.user_id as i32) {
-            Ok(token) => HttpResponse::Ok()
-                .insert_header(("x-token", token))
-                .json(user),
-            Err(e) => {
-                error!("Failed to generate JWT: {}", e);
-                HttpResponse::InternalServerError().finish()
-            }
-        },
-        None => {
-            error!("Login failed: Invalid email or password");
-            HttpResponse::Unauthorized().finish()
-        }
-    }
+    let user = sql_operations::login(email, password).await?;
+    let role = Role::try_from(user.user_type_id)?;
+    let tokens = session::create_session(user.user_id, role).await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("x-token", tokens.access_token))
+        .insert_header(("x-refresh-token", tokens.refresh_token))
+        .json(user))
+}
+
+/// Exchanges a refresh token for a new access token and a rotated refresh token.
+#[utoipa::path(
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "Tokens refreshed successfully.", body = TokenPair),
+        (status = 401, description = "Unauthorized. Invalid, expired or reused refresh token."),
+        (status = 500, description = "Internal server error.")
+    )
+)]
+#[post("/token/refresh")]
+pub async fn refresh_token(data: web::Json<RefreshRequest>) -> Result<HttpResponse, AppError> {
+    let tokens = session::refresh_session(&data.refresh_token).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Logs out the current session, invalidating its access and refresh tokens.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Logged out successfully."),
+        (status = 401, description = "Unauthorized. Invalid token."),
+        (status = 500, description = "Internal server error.")
+    )
+)]
+#[post("/logout")]
+pub async fn logout(claims: web::ReqData<Claims>) -> Result<HttpResponse, AppError> {
+    session::revoke_session(claims.into_inner().sid).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Logs out every session of the current user.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "All sessions logged out successfully."),
+        (status = 401, description = "Unauthorized. Invalid token."),
+        (status = 500, description = "Internal server error.")
+    )
+)]
+#[post("/logout/all")]
+pub async fn logout_all(user: AuthenticatedUser) -> Result<HttpResponse, AppError> {
+    session::revoke_all_sessions(user.user_id).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Generates a verification code and sends it to the specified email.
@@ -45,18 +88,89 @@ pub async fn login_user(login_data: web::Json<LoginData>) -> impl Responder {
     request_body = email,
     responses(
         (status = 200, description = "Verification code sent successfully."),
+        (status = 429, description = "Too many verification requests for this email or address."),
         (status = 500, description = "Internal server error.")
     )
 )]
 #[post("/user/verification/request")]
-pub async fn request_verification(email: web::Json<String>) -> impl Responder {
+pub async fn request_verification(
+    req: HttpRequest,
+    sender: web::Data<dyn EmailSender>,
+    email: web::Json<String>,
+) -> Result<HttpResponse, AppError> {
+    send_new_code(
+        &req,
+        sender.get_ref(),
+        email.into_inner(),
+        CodePurpose::VerifyEmail,
+    )
+    .await
+}
+
+/// Proxies whose `X-Forwarded-For` header is believed, from the comma separated
+/// `TRUSTED_PROXIES`. Without it the header is ignored, as any client can set it.
+fn trusted_proxies() -> Vec<IpAddr> {
+    std::env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|proxy| proxy.trim().parse().ok())
+        .collect()
+}
+
+/// The address verification requests are throttled by: the peer, or when the peer is a
+/// trusted proxy, the nearest address before it in `X-Forwarded-For` that isn't one.
+fn client_ip(req: &HttpRequest) -> String {
+    let Some(peer) = req.peer_addr().map(|addr| addr.ip()) else {
+        return "unknown".to_string();
+    };
+    let forwarded: Vec<&str> = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+
+    forwarded_client(peer, &forwarded.join(","), &trusted_proxies()).to_string()
+}
+
+fn forwarded_client(peer: IpAddr, forwarded_for: &str, trusted: &[IpAddr]) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+
+    // Each proxy appends the address it received the request from, so only the entries
+    // added by trusted proxies, read from the right, can be believed.
+    let mut client = peer;
+    for hop in forwarded_for.rsplit(',') {
+        match hop.trim().parse() {
+            Ok(address) => client = address,
+            Err(_) => break,
+        }
+        if !trusted.contains(&client) {
+            break;
+        }
+    }
+    client
+}
+
+async fn send_new_code(
+    req: &HttpRequest,
+    sender: &dyn EmailSender,
+    email: String,
+    purpose: CodePurpose,
+) -> Result<HttpResponse, AppError> {
+    let ip = client_ip(req);
+
+    sql_operations::throttle_verification_request(email.clone(), ip).await?;
+
     let code = generate_verification_code();
-    
-    send_verification_email(email.clone(), code.clone()).await;
+    sql_operations::store_verification_code(email.clone(), purpose, code.clone()).await?;
 
-    VERIFICATION_CODES.lock().unwrap().insert(email.clone(), code);
+    match purpose {
+        CodePurpose::VerifyEmail => send_verification_email(sender, email, code).await?,
+        CodePurpose::ResetPassword => send_password_reset_email(sender, email, code).await?,
+    }
 
-    HttpResponse::Ok().finish()
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Verifies the code sent to the user's email.
@@ -64,21 +178,131 @@ pub async fn request_verification(email: web::Json<String>) -> impl Responder {
     request_body = VerificationRequest,
     responses(
         (status = 200, description = "Verification successful."),
-        (status = 401, description = "Unauthorized. Invalid or expired code.")
+        (status = 401, description = "Unauthorized. Invalid or expired code."),
+        (status = 429, description = "Too many failed attempts. A new code must be requested.")
     )
 )]
 #[post("/user/verify")]
-pub async fn verify_code(data: web::Json<VerificationRequest>) -> impl Responder {
+pub async fn verify_code(data: web::Json<VerificationRequest>) -> Result<HttpResponse, AppError> {
     let VerificationRequest { email, code } = data.into_inner();
 
-    let mut codes = VERIFICATION_CODES.lock().unwrap();
-    
-    if let Some(stored_code) = codes.get(&email) {
-        if stored_code == &code {
-            codes.remove(&email);
-            return HttpResponse::Ok().finish();
-        }
+    sql_operations::check_verification_code(email.clone(), CodePurpose::VerifyEmail, code).await?;
+    sql_operations::mark_email_verified(email).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Sends a new verification code to an account that has not confirmed its email yet.
+#[utoipa::path(
+    request_body = email,
+    responses(
+        (status = 200, description = "Verification code sent successfully."),
+        (status = 404, description = "No account is registered with this email."),
+        (status = 409, description = "Email address is already verified."),
+        (status = 429, description = "Too many verification requests for this email or address."),
+        (status = 500, description = "Internal server error.")
+    )
+)]
+#[post("/user/verification/resend")]
+pub async fn resend_verification(
+    req: HttpRequest,
+    sender: web::Data<dyn EmailSender>,
+    email: web::Json<String>,
+) -> Result<HttpResponse, AppError> {
+    if !sql_operations::is_pending_verification(email.clone()).await? {
+        return Err(AppError::Conflict(
+            "Email address is already verified.".to_string(),
+        ));
     }
 
-    HttpResponse::Unauthorized().finish()
-}
\ No newline at end of file
+    send_new_code(
+        &req,
+        sender.get_ref(),
+        email.into_inner(),
+        CodePurpose::VerifyEmail,
+    )
+    .await
+}
+
+/// Sends a code to reset the password of an account whose password was forgotten.
+#[utoipa::path(
+    request_body = email,
+    responses(
+        (status = 200, description = "Password reset code sent successfully."),
+        (status = 404, description = "No account is registered with this email."),
+        (status = 429, description = "Too many verification requests for this email or address."),
+        (status = 500, description = "Internal server error.")
+    )
+)]
+#[post("/password/reset/request")]
+pub async fn request_password_reset(
+    req: HttpRequest,
+    sender: web::Data<dyn EmailSender>,
+    email: web::Json<String>,
+) -> Result<HttpResponse, AppError> {
+    sql_operations::get_user_id(email.clone()).await?;
+
+    send_new_code(
+        &req,
+        sender.get_ref(),
+        email.into_inner(),
+        CodePurpose::ResetPassword,
+    )
+    .await
+}
+
+/// Sets a new password with a code from `/password/reset/request`, and logs out every
+/// session of the account.
+#[utoipa::path(
+    request_body = PasswordResetRequest,
+    responses(
+        (status = 200, description = "Password reset successfully."),
+        (status = 401, description = "Unauthorized. Invalid or expired code."),
+        (status = 404, description = "No account is registered with this email."),
+        (status = 429, description = "Too many failed attempts. A new code must be requested."),
+        (status = 500, description = "Internal server error.")
+    )
+)]
+#[post("/password/reset")]
+pub async fn reset_password(
+    data: web::Json<PasswordResetRequest>,
+) -> Result<HttpResponse, AppError> {
+    let PasswordResetRequest {
+        email,
+        code,
+        password,
+    } = data.into_inner();
+
+    let user_id = sql_operations::get_user_id(email.clone()).await?;
+    sql_operations::check_verification_code(email, CodePurpose::ResetPassword, code).await?;
+    sql_operations::reset_password(user_id, password).await?;
+    session::revoke_all_sessions(user_id).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_forwarded_client_untrusted_peer() {
+        let client = forwarded_client(ip("203.0.113.7"), "198.51.100.1", &[ip("10.0.0.1")]);
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_forwarded_client_trusted_proxy() {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+
+        // The client spoofed the first entry; the proxies appended the rest.
+        let client = forwarded_client(ip("10.0.0.1"), "1.2.3.4, 203.0.113.7, 10.0.0.2", &trusted);
+        assert_eq!(client, ip("203.0.113.7"));
+
+        let client = forwarded_client(ip("10.0.0.1"), "", &trusted);
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+}
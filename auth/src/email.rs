@@ -0,0 +1,286 @@
+use async_trait::async_trait;
+use data_access::{AppError, AppResult};
+use dotenvy::dotenv;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use reqwest::Client;
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const DEFAULT_FROM: &str = "studyvaultuv@gmail.com";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers plain-text emails. Pick an implementation with [`from_env`].
+#[async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, message: &EmailMessage) -> AppResult<()>;
+}
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+fn required_env(name: &str) -> AppResult<String> {
+    std::env::var(name).map_err(|_| AppError::Internal(format!("{} is not set", name)))
+}
+
+/// Builds the sender selected by `EMAIL_TRANSPORT`: `sendgrid` (default), `smtp`, `outbox`
+/// or `memory`.
+pub fn from_env() -> AppResult<Arc<dyn EmailSender>> {
+    dotenv().ok();
+    let from = env_or("EMAIL_FROM", DEFAULT_FROM);
+
+    let sender: Arc<dyn EmailSender> = match env_or("EMAIL_TRANSPORT", "sendgrid").as_str() {
+        "sendgrid" => Arc::new(SendGridSender::new(
+            std::env::var("SENDGRID_API_KEY").or_else(|_| required_env("API_KEY"))?,
+            from,
+        )),
+        "smtp" => Arc::new(SmtpSender::from_env(from)?),
+        "outbox" => Arc::new(OutboxSender::new(
+            env_or("EMAIL_OUTBOX_DIR", "outbox"),
+            from,
+        )),
+        "memory" => Arc::new(InMemorySender::default()),
+        other => {
+            return Err(AppError::Internal(format!(
+                "Unknown EMAIL_TRANSPORT \"{}\"",
+                other
+            )))
+        }
+    };
+
+    Ok(sender)
+}
+
+/// Sends through the SendGrid v3 HTTP API.
+pub struct SendGridSender {
+    client: Client,
+    api_key: String,
+    from: String,
+}
+
+impl SendGridSender {
+    pub fn new(api_key: String, from: String) -> Self {
+        SendGridSender {
+            client: Client::new(),
+            api_key,
+            from,
+        }
+    }
+}
+
+#[async_trait]
+impl EmailSender for SendGridSender {
+    async fn send(&self, message: &EmailMessage) -> AppResult<()> {
+        let body = json!({
+            "personalizations": [{
+                "to": [{ "email": message.to }],
+                "subject": message.subject
+            }],
+            "from": { "email": self.from },
+            "content": [{
+                "type": "text/plain",
+                "value": message.body
+            }]
+        });
+
+        let response = self
+            .client
+            .post("https://api.sendgrid.com/v3/mail/send")
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| AppError::Internal(format!("SendGrid request failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(AppError::Internal(format!(
+                "SendGrid rejected the email: {}",
+                response.status()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends through an SMTP relay configured with `SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME`,
+/// `SMTP_PASSWORD` and `SMTP_TLS` (`starttls` by default, `tls` or `none`).
+pub struct SmtpSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpSender {
+    pub fn from_env(from: String) -> AppResult<Self> {
+        let host = required_env("SMTP_HOST")?;
+        let smtp_error = |e: lettre::transport::smtp::Error| {
+            AppError::Internal(format!("Invalid SMTP configuration: {}", e))
+        };
+
+        let mut builder = match env_or("SMTP_TLS", "starttls").as_str() {
+            "starttls" => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host).map_err(smtp_error)?
+            }
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&host).map_err(smtp_error)?,
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+            other => {
+                return Err(AppError::Internal(format!(
+                    "Unknown SMTP_TLS \"{}\"",
+                    other
+                )))
+            }
+        };
+
+        if let Ok(port) = std::env::var("SMTP_PORT") {
+            let port = port
+                .parse()
+                .map_err(|_| AppError::Internal(format!("Invalid SMTP_PORT \"{}\"", port)))?;
+            builder = builder.port(port);
+        }
+        if let Ok(username) = std::env::var("SMTP_USERNAME") {
+            builder = builder.credentials(Credentials::new(username, env_or("SMTP_PASSWORD", "")));
+        }
+
+        Ok(SmtpSender {
+            transport: builder.build(),
+            from: parse_mailbox(&from)?,
+        })
+    }
+}
+
+fn parse_mailbox(address: &str) -> AppResult<Mailbox> {
+    address
+        .parse()
+        .map_err(|_| AppError::Validation(format!("Invalid email address \"{}\".", address)))
+}
+
+#[async_trait]
+impl EmailSender for SmtpSender {
+    async fn send(&self, message: &EmailMessage) -> AppResult<()> {
+        let email = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(parse_mailbox(&message.to)?)
+            .subject(message.subject.clone())
+            .body(message.body.clone())
+            .map_err(|e| AppError::Internal(format!("Failed to build email: {}", e)))?;
+
+        self.transport
+            .send(email)
+            .await
+            .map_err(|e| AppError::Internal(format!("SMTP delivery failed: {}", e)))?;
+
+        Ok(())
+    }
+}
+
+/// Writes each email as a `.eml` file into a directory instead of sending it, for running
+/// the verification flow offline.
+pub struct OutboxSender {
+    dir: PathBuf,
+    from: String,
+}
+
+impl OutboxSender {
+    pub fn new(dir: impl Into<PathBuf>, from: String) -> Self {
+        OutboxSender {
+            dir: dir.into(),
+            from,
+        }
+    }
+}
+
+#[async_trait]
+impl EmailSender for OutboxSender {
+    async fn send(&self, message: &EmailMessage) -> AppResult<()> {
+        let suffix: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect();
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            suffix
+        ));
+        let contents = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+            self.from, message.to, message.subject, message.body
+        );
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to create outbox: {}", e)))?;
+        tokio::fs::write(&path, contents)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to write {:?}: {}", path, e)))?;
+
+        Ok(())
+    }
+}
+
+/// Keeps sent emails in memory so tests can read them back.
+#[derive(Default, Clone)]
+pub struct InMemorySender {
+    messages: Arc<Mutex<Vec<EmailMessage>>>,
+}
+
+impl InMemorySender {
+    pub fn messages(&self) -> Vec<EmailMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl EmailSender for InMemorySender {
+    async fn send(&self, message: &EmailMessage) -> AppResult<()> {
+        self.messages.lock().unwrap().push(message.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> EmailMessage {
+        EmailMessage {
+            to: "lizrm@uv.mx".to_string(),
+            subject: "Verification code for Study Vault".to_string(),
+            body: "Your verification code is: A1B2C3".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_sender() {
+        let sender = InMemorySender::default();
+        sender.send(&message()).await.unwrap();
+
+        assert_eq!(sender.messages(), vec![message()]);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_sender_writes_file() {
+        let dir = std::env::temp_dir().join(format!("outbox-{}", std::process::id()));
+        let sender = OutboxSender::new(&dir, DEFAULT_FROM.to_string());
+        sender.send(&message()).await.unwrap();
+
+        let mut entries = std::fs::read_dir(&dir).unwrap();
+        let path = entries.next().unwrap().unwrap().path();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(contents.contains("To: lizrm@uv.mx"));
+        assert!(contents.contains("Your verification code is: A1B2C3"));
+    }
+}
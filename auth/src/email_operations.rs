@@ -1,16 +1,8 @@
-use dotenvy::dotenv;
-use serde_json::json;
-use reqwest::Client;
-use std::env;
+use auth::email::{EmailMessage, EmailSender};
+use data_access::AppResult;
 use rand::distributions::Alphanumeric;
 use rand::thread_rng;
 use rand::Rng;
-use std::collections::HashMap;
-use std::sync::Mutex;
-
-lazy_static::lazy_static! {
-    pub static ref VERIFICATION_CODES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
-}
 
 pub fn generate_verification_code() -> String {
     let code: String = thread_rng()
@@ -21,38 +13,33 @@ pub fn generate_verification_code() -> String {
     code
 }
 
-pub async fn send_verification_email(email: String, code: String) {
-    dotenv().ok();
-    let api_key = env::var("API_KEY").expect("API_KEY not found");
-    let from_email = "studyvaultuv@gmail.com";
+pub async fn send_verification_email(
+    sender: &dyn EmailSender,
+    email: String,
+    code: String,
+) -> AppResult<()> {
+    let message = EmailMessage {
+        to: email,
+        subject: "Verification code for Study Vault".to_string(),
+        body: format!("Your verification code is: {}", code),
+    };
 
-    let body = json!({
-        "personalizations": [{
-            "to": [{ "email": email }],
-            "subject": "Verification code for Study Vault"
-        }],
-        "from": { "email": from_email },
-        "content": [{
-            "type": "text/plain",
-            "value": format!("Your verification code is: {}", code)
-        }]
-    });
+    sender.send(&message).await
+}
 
-    let client = Client::new();
-    let response = client.post("https://api.sendgrid.com/v3/mail/send")
-        .bearer_auth(api_key)
-        .json(&body)
-        .send()
-        .await;
+pub async fn send_password_reset_email(
+    sender: &dyn EmailSender,
+    email: String,
+    code: String,
+) -> AppResult<()> {
+    let message = EmailMessage {
+        to: email,
+        subject: "Password reset code for Study Vault".to_string(),
+        body: format!(
+            "Your password reset code is: {}\nIf you didn't ask to reset your password, ignore this email.",
+            code
+        ),
+    };
 
-    match response {
-        Ok(res) => {
-            if res.status().is_success() {
-                println!("Email sent successfully!");
-            } else {
-                println!("Failed to send email: {}", res.status());
-            }
-        }
-        Err(e) => println!("Error: {:?}", e),
-    }
-}
\ No newline at end of file
+    sender.send(&message).await
+}
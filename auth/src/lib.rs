@@ -1,32 +1,53 @@
-use actix_web::dev::ServiceRequest;
-use actix_web::error::ErrorUnauthorized;
-use actix_web::Error;
+pub mod email;
+pub mod password;
+pub mod role;
+pub mod session;
+
+pub use role::{Role, RoleGuard};
+
+use actix_web::dev::{Payload, ServiceRequest};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
+use data_access::{AppError, AppResult};
 use dotenvy::dotenv;
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use jsonwebtoken::{encode, EncodingKey, Header};
 use serde::{Deserialize, Serialize};
+use std::future::{ready, Ready};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
-    sub: i32,
-    exp: usize,
+    pub sub: i32,
+    pub role: Role,
+    /// Session the token was issued for, checked against `sessions` to honour logouts.
+    pub sid: String,
+    pub exp: usize,
 }
 
-pub fn generate_jwt(user_id: i32) -> Result<String, jsonwebtoken::errors::Error> {
+pub fn generate_jwt(
+    user_id: i32,
+    role: Role,
+    session_id: String,
+) -> Result<String, jsonwebtoken::errors::Error> {
     dotenv().ok();
     let secret_key =
         std::env::var("SECRET_KEY").expect("Couldn't get secret key from cargo environment");
+    let ttl_secs: usize = std::env::var("ACCESS_TOKEN_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(900); // Expire in 15 min, refreshed through /token/refresh
 
     let expiration = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("Time error")
         .as_secs() as usize
-        + 7200; // Expire in 2 hrs
+        + ttl_secs;
 
     let claims = Claims {
         sub: user_id,
+        role,
+        sid: session_id,
         exp: expiration,
     };
 
@@ -37,23 +58,100 @@ pub fn generate_jwt(user_id: i32) -> Result<String, jsonwebtoken::errors::Error>
     )
 }
 
-pub async fn validate_jwt(
-    req: ServiceRequest,
-    credentials: BearerAuth,
-) -> Result<ServiceRequest, Error> {
+pub fn decode_jwt(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     dotenv().ok();
 
     let secret_key =
         std::env::var("SECRET_KEY").expect("Couldn't get secret key from cargo environment");
 
-    let token = credentials.token();
-
-    match decode::<Claims>(
+    decode::<Claims>(
         token,
         &DecodingKey::from_secret(secret_key.as_ref()),
         &Validation::default(),
-    ) {
-        Ok(_) => Ok(req),
-        Err(_) => Err(ErrorUnauthorized("Invalid token")),
+    )
+    .map(|data| data.claims)
+}
+
+/// Decodes a token and rejects it if its session was revoked or has expired.
+pub async fn authenticate(token: &str) -> AppResult<Claims> {
+    let claims =
+        decode_jwt(token).map_err(|_| AppError::Unauthorized("Invalid token.".to_string()))?;
+    check_session(&claims).await?;
+    Ok(claims)
+}
+
+/// Rejects already-decoded claims whose session was revoked or has expired.
+pub async fn check_session(claims: &Claims) -> AppResult<()> {
+    if !session::is_session_active(claims.sid.clone()).await? {
+        return Err(AppError::Unauthorized(
+            "Session has been revoked.".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Bearer validator for `HttpAuthentication`. Stores the decoded [`Claims`] in the request
+/// extensions so handlers can take an [`AuthenticatedUser`].
+pub async fn validate_jwt(
+    req: ServiceRequest,
+    credentials: BearerAuth,
+) -> Result<ServiceRequest, Error> {
+    let claims = authenticate(credentials.token()).await?;
+    req.extensions_mut().insert(claims);
+    Ok(req)
+}
+
+/// The caller identified by the bearer token. Only available on routes wrapped with
+/// `HttpAuthentication::bearer(validate_jwt)`.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: u32,
+    pub role: Role,
+}
+
+impl AuthenticatedUser {
+    pub fn from_claims(claims: &Claims) -> Self {
+        AuthenticatedUser {
+            user_id: claims.sub as u32,
+            role: claims.role,
+        }
+    }
+
+    /// Fails with `Forbidden` unless the caller owns the resource or is an administrator.
+    pub fn ensure_owner(&self, owner_id: u32) -> AppResult<()> {
+        if self.user_id == owner_id || self.role == Role::Administrator {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(
+                "You are not allowed to modify this resource.".to_string(),
+            ))
+        }
+    }
+
+    /// Handler-level counterpart of [`RoleGuard`].
+    pub fn ensure_role(&self, allowed: &[Role]) -> AppResult<()> {
+        if self.role.is_allowed(allowed) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(
+                "Your role is not allowed to perform this action.".to_string(),
+            ))
+        }
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user = req
+            .extensions()
+            .get::<Claims>()
+            .map(AuthenticatedUser::from_claims)
+            .ok_or_else(|| AppError::Unauthorized("Missing bearer token.".to_string()).into());
+
+        ready(user)
     }
 }
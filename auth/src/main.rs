@@ -4,12 +4,17 @@ mod email_operations;
 mod sql_operations;
 
 use actix_cors::Cors;
-use actix_web::{App, HttpServer};
+use actix_web::{web, App, HttpServer};
+use actix_web_httpauth::middleware::HttpAuthentication;
+use ::auth::validate_jwt;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    data_access::get_pool().expect("Failed to create database pool");
+    let email_sender = ::auth::email::from_env().expect("Failed to configure email transport");
+
     HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
@@ -20,23 +25,48 @@ async fn main() -> std::io::Result<()> {
         #[openapi(
             paths(
                 controller::login_user,
+                controller::refresh_token,
+                controller::logout,
+                controller::logout_all,
                 controller::request_verification,
+                controller::resend_verification,
                 controller::verify_code,
+                controller::request_password_reset,
+                controller::reset_password,
             ),
-            components(schemas(auth::LoginData, auth::VerificationRequest))
+            components(schemas(
+                auth::LoginData,
+                auth::VerificationRequest,
+                auth::PasswordResetRequest,
+                auth::RefreshRequest,
+                ::auth::session::TokenPair
+            ))
         )]
         struct ApiDoc;
 
         let openapi = ApiDoc::openapi();
 
         App::new()
+            .app_data(web::Data::from(email_sender.clone()))
+            .app_data(data_access::error::json_config())
+            .app_data(data_access::error::path_config())
             .wrap(cors)
             .service(
                 SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", openapi.clone()),
             )
             .service(controller::login_user)
+            .service(controller::refresh_token)
             .service(controller::request_verification)
+            .service(controller::resend_verification)
             .service(controller::verify_code)
+            .service(controller::request_password_reset)
+            .service(controller::reset_password)
+            .service(
+                web::scope("")
+                    .wrap(HttpAuthentication::bearer(validate_jwt))
+                    .service(controller::logout)
+                    .service(controller::logout_all),
+            )
     })
     .bind("0.0.0.0:8085")?
     .run()
@@ -0,0 +1,151 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use sha2::{Digest, Sha256};
+
+/// Outcome of checking a password against the value stored in `users.password`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Valid,
+    /// The password matched a legacy plaintext or SHA-256 value, or an Argon2id hash of
+    /// a non-canonical form, and should be replaced with a fresh hash.
+    ValidNeedsRehash,
+    Invalid,
+}
+
+/// Hashes the canonical form of a password (see [`canonical_password`]) with Argon2id and a
+/// random salt, returning a PHC string.
+///
+/// Hashing is CPU bound, so it runs on the blocking thread pool.
+pub async fn hash_password(password: String) -> Result<String, argon2::password_hash::Error> {
+    tokio::task::spawn_blocking(move || hash_password_blocking(&password))
+        .await
+        .expect("Password hashing task panicked")
+}
+
+/// Checks `password` against a stored Argon2id hash, or against a legacy
+/// plaintext / SHA-256 hex value left over from before server-side hashing.
+pub async fn verify_password(password: String, stored: String) -> Verification {
+    tokio::task::spawn_blocking(move || verify_password_blocking(&password, &stored))
+        .await
+        .expect("Password verification task panicked")
+}
+
+/// Some clients send the SHA-256 hex digest of the password and others the plaintext, so
+/// both are reduced to the lowercase digest before hashing. Input that already is 64 hex
+/// characters is taken to be the digest.
+pub fn canonical_password(password: &str) -> String {
+    if password.len() == 64 && password.bytes().all(|b| b.is_ascii_hexdigit()) {
+        password.to_ascii_lowercase()
+    } else {
+        sha256_hex(password)
+    }
+}
+
+fn hash_password_blocking(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(canonical_password(password).as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+fn verify_password_blocking(password: &str, stored: &str) -> Verification {
+    if let Ok(hash) = PasswordHash::new(stored) {
+        let argon2 = Argon2::default();
+        let canonical = canonical_password(password);
+        if argon2.verify_password(canonical.as_bytes(), &hash).is_ok() {
+            return Verification::Valid;
+        }
+        // Hashes written before passwords were canonicalized hold the form the client sent.
+        if canonical != password && argon2.verify_password(password.as_bytes(), &hash).is_ok() {
+            return Verification::ValidNeedsRehash;
+        }
+        return Verification::Invalid;
+    }
+
+    // Legacy rows hold either the plaintext password or the SHA-256 hex digest the
+    // client sends, so accept the password matching the row in either direction.
+    let matches_legacy = stored == password
+        || stored.eq_ignore_ascii_case(&sha256_hex(password))
+        || password.eq_ignore_ascii_case(&sha256_hex(stored));
+
+    if matches_legacy {
+        Verification::ValidNeedsRehash
+    } else {
+        Verification::Invalid
+    }
+}
+
+fn sha256_hex(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JUAN_SHA256: &str = "ed08c290d7e22f7bb324b15cbadce35b0b348564fd2d5f95752388d86d71bcca";
+
+    #[tokio::test]
+    async fn test_hash_and_verify() {
+        let hash = hash_password("secret".to_string()).await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+
+        let result = verify_password("secret".to_string(), hash.clone()).await;
+        assert_eq!(result, Verification::Valid);
+
+        let result = verify_password("wrong".to_string(), hash).await;
+        assert_eq!(result, Verification::Invalid);
+    }
+
+    #[tokio::test]
+    async fn test_verify_legacy_plaintext() {
+        let result = verify_password("123456".to_string(), "123456".to_string()).await;
+        assert_eq!(result, Verification::ValidNeedsRehash);
+    }
+
+    #[tokio::test]
+    async fn test_verify_legacy_sha256() {
+        let result = verify_password(JUAN_SHA256.to_string(), JUAN_SHA256.to_string()).await;
+        assert_eq!(result, Verification::ValidNeedsRehash);
+
+        let result = verify_password("juan".to_string(), JUAN_SHA256.to_string()).await;
+        assert_eq!(result, Verification::ValidNeedsRehash);
+    }
+
+    #[tokio::test]
+    async fn test_verify_legacy_plaintext_with_hashed_input() {
+        let result = verify_password(sha256_hex("123456"), "123456".to_string()).await;
+        assert_eq!(result, Verification::ValidNeedsRehash);
+    }
+
+    #[tokio::test]
+    async fn test_hash_accepts_both_forms() {
+        let hash = hash_password("juan".to_string()).await.unwrap();
+        let result = verify_password(JUAN_SHA256.to_string(), hash.clone()).await;
+        assert_eq!(result, Verification::Valid);
+
+        let hash = hash_password(JUAN_SHA256.to_uppercase()).await.unwrap();
+        let result = verify_password("juan".to_string(), hash).await;
+        assert_eq!(result, Verification::Valid);
+    }
+
+    #[test]
+    fn test_verify_uncanonical_argon2_hash() {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(b"juan", &salt)
+            .unwrap()
+            .to_string();
+
+        let result = verify_password_blocking("juan", &hash);
+        assert_eq!(result, Verification::ValidNeedsRehash);
+        let result = verify_password_blocking(JUAN_SHA256, &hash);
+        assert_eq!(result, Verification::Invalid);
+    }
+
+    #[tokio::test]
+    async fn test_verify_legacy_invalid() {
+        let result = verify_password("654321".to_string(), "123456".to_string()).await;
+        assert_eq!(result, Verification::Invalid);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::Claims;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpMessage};
+use data_access::AppError;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// User types stored in `user_types`. The discriminants match `user_type_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Professor = 1,
+    Student = 2,
+    Administrator = 3,
+}
+
+impl Role {
+    /// Administrators pass every role check.
+    pub fn is_allowed(self, allowed: &[Role]) -> bool {
+        self == Role::Administrator || allowed.contains(&self)
+    }
+}
+
+impl TryFrom<u32> for Role {
+    type Error = AppError;
+
+    fn try_from(user_type_id: u32) -> Result<Self, Self::Error> {
+        match user_type_id {
+            1 => Ok(Role::Professor),
+            2 => Ok(Role::Student),
+            3 => Ok(Role::Administrator),
+            _ => Err(AppError::Internal(format!(
+                "Unknown user type id {}",
+                user_type_id
+            ))),
+        }
+    }
+}
+
+/// Route middleware that only lets callers with one of the given roles through.
+///
+/// Must run inside `HttpAuthentication::bearer(validate_jwt)`, e.g.
+/// `#[post("/channel/create", wrap = "RoleGuard::allow(&[Role::Professor])")]`.
+pub struct RoleGuard {
+    allowed: &'static [Role],
+}
+
+impl RoleGuard {
+    pub fn allow(allowed: &'static [Role]) -> Self {
+        RoleGuard { allowed }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RoleGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RoleGuardMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RoleGuardMiddleware {
+            service: Rc::new(service),
+            allowed: self.allowed,
+        }))
+    }
+}
+
+pub struct RoleGuardMiddleware<S> {
+    service: Rc<S>,
+    allowed: &'static [Role],
+}
+
+impl<S, B> Service<ServiceRequest> for RoleGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let role = req.extensions().get::<Claims>().map(|claims| claims.role);
+
+        match role {
+            Some(role) if role.is_allowed(self.allowed) => {
+                let service = Rc::clone(&self.service);
+                Box::pin(async move { service.call(req).await })
+            }
+            Some(_) => Box::pin(ready(Err(AppError::Forbidden(
+                "Your role is not allowed to perform this action.".to_string(),
+            )
+            .into()))),
+            None => Box::pin(ready(Err(AppError::Unauthorized(
+                "Missing bearer token.".to_string(),
+            )
+            .into()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_is_allowed() {
+        assert!(Role::Professor.is_allowed(&[Role::Professor]));
+        assert!(!Role::Student.is_allowed(&[Role::Professor]));
+        assert!(Role::Administrator.is_allowed(&[Role::Professor]));
+    }
+
+    #[test]
+    fn test_role_from_user_type_id() {
+        assert_eq!(Role::try_from(1).unwrap(), Role::Professor);
+        assert_eq!(Role::try_from(2).unwrap(), Role::Student);
+        assert!(Role::try_from(9).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{generate_jwt, Role};
+use data_access::{AppError, AppResult};
+use mysql::{params, prelude::Queryable, Row};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+/// Refresh tokens live for `REFRESH_TOKEN_TTL_DAYS` (30 by default) since their last rotation.
+fn refresh_token_ttl_days() -> u32 {
+    std::env::var("REFRESH_TOKEN_TTL_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TokenPair {
+    pub access_token: String,
+    /// Opaque `{session_id}.{secret}` value. Only its SHA-256 is stored server-side.
+    pub refresh_token: String,
+}
+
+fn random_string(len: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn invalid_refresh_token() -> AppError {
+    AppError::Unauthorized("Invalid or expired refresh token.".to_string())
+}
+
+fn issue_tokens(session_id: &str, secret: &str, user_id: u32, role: Role) -> AppResult<TokenPair> {
+    let access_token = generate_jwt(user_id as i32, role, session_id.to_string())
+        .map_err(|e| AppError::Internal(format!("Failed to generate JWT: {}", e)))?;
+
+    Ok(TokenPair {
+        access_token,
+        refresh_token: format!("{}.{}", session_id, secret),
+    })
+}
+
+/// Starts a new session for a user who just logged in.
+pub async fn create_session(user_id: u32, role: Role) -> AppResult<TokenPair> {
+    let session_id = random_string(32);
+    let secret = random_string(48);
+    let secret_hash = hash_secret(&secret);
+    let ttl_days = refresh_token_ttl_days();
+
+    let query = "INSERT INTO sessions (session_id, user_id, refresh_token_hash, created_at, expires_at)
+        VALUES (:session_id, :user_id, :refresh_token_hash, NOW(), NOW() + INTERVAL :ttl_days DAY)";
+
+    let id = session_id.clone();
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(
+            query,
+            params! {
+                "session_id" => id,
+                "user_id" => user_id,
+                "refresh_token_hash" => secret_hash,
+                "ttl_days" => ttl_days,
+            },
+        )
+    })
+    .await?;
+
+    issue_tokens(&session_id, &secret, user_id, role)
+}
+
+/// What presenting a refresh token did.
+enum Refresh {
+    /// The token was current and has been rotated; carries the session's user.
+    Rotated(Row),
+    /// The token was valid once but has already been rotated away.
+    Reused,
+    Invalid,
+}
+
+/// Exchanges a refresh token for a new access token and a new refresh token.
+///
+/// Presenting a token that was already rotated away revokes the whole session, since it
+/// means the token was copied. Any other mismatch is rejected without touching the
+/// session, as session ids can be read from access tokens.
+pub async fn refresh_session(refresh_token: &str) -> AppResult<TokenPair> {
+    let (session_id, secret) = refresh_token
+        .split_once('.')
+        .ok_or_else(invalid_refresh_token)?;
+    let session_id = session_id.to_string();
+    let old_hash = hash_secret(secret);
+    let new_secret = random_string(48);
+    let new_hash = hash_secret(&new_secret);
+    let ttl_days = refresh_token_ttl_days();
+
+    let rotate_query = "UPDATE sessions
+        SET refresh_token_hash = :new_hash, expires_at = NOW() + INTERVAL :ttl_days DAY
+        WHERE session_id = :session_id AND refresh_token_hash = :old_hash
+        AND revoked_at IS NULL AND expires_at > NOW()";
+    let history_query = "INSERT INTO rotated_refresh_tokens
+        (refresh_token_hash, session_id, rotated_at) VALUES (:old_hash, :session_id, NOW())";
+    let reused_query = "SELECT COUNT(*) FROM rotated_refresh_tokens
+        WHERE refresh_token_hash = :old_hash AND session_id = :session_id";
+    let user_query = "SELECT users.user_id, users.user_type_id
+        FROM sessions INNER JOIN users ON sessions.user_id = users.user_id
+        WHERE sessions.session_id = :session_id";
+
+    let id = session_id.clone();
+    let refresh = data_access::with_connection(move |conn| {
+        let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
+        let rotated = transaction
+            .exec_iter(
+                rotate_query,
+                params! {
+                    "new_hash" => new_hash,
+                    "ttl_days" => ttl_days,
+                    "session_id" => &id,
+                    "old_hash" => &old_hash,
+                },
+            )?
+            .affected_rows();
+
+        if rotated == 0 {
+            let reused: Option<u32> = transaction.exec_first(
+                reused_query,
+                params! { "old_hash" => &old_hash, "session_id" => &id },
+            )?;
+            return Ok(match reused.unwrap_or_default() {
+                0 => Refresh::Invalid,
+                _ => Refresh::Reused,
+            });
+        }
+
+        transaction.exec_drop(
+            history_query,
+            params! { "old_hash" => &old_hash, "session_id" => &id },
+        )?;
+        let row: Option<Row> =
+            transaction.exec_first(user_query, params! { "session_id" => &id })?;
+        transaction.commit()?;
+
+        Ok::<_, mysql::Error>(row.map_or(Refresh::Invalid, Refresh::Rotated))
+    })
+    .await?;
+
+    match refresh {
+        Refresh::Rotated(mut row) => {
+            let user_id: u32 = row.take("user_id").unwrap();
+            let user_type_id: u32 = row.take("user_type_id").unwrap();
+            issue_tokens(
+                &session_id,
+                &new_secret,
+                user_id,
+                Role::try_from(user_type_id)?,
+            )
+        }
+        Refresh::Reused => {
+            revoke_session(session_id).await?;
+            Err(invalid_refresh_token())
+        }
+        Refresh::Invalid => Err(invalid_refresh_token()),
+    }
+}
+
+/// Revokes a single session, e.g. on logout.
+pub async fn revoke_session(session_id: String) -> AppResult<()> {
+    let query = "UPDATE sessions SET revoked_at = NOW()
+        WHERE session_id = :session_id AND revoked_at IS NULL";
+
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(query, params! { "session_id" => session_id })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Revokes every session of a user ("log out all sessions").
+pub async fn revoke_all_sessions(user_id: u32) -> AppResult<()> {
+    let query = "UPDATE sessions SET revoked_at = NOW()
+        WHERE user_id = :user_id AND revoked_at IS NULL";
+
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(query, params! { "user_id" => user_id })
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Whether access tokens issued for this session should still be accepted.
+pub async fn is_session_active(session_id: String) -> AppResult<bool> {
+    let query = "SELECT COUNT(*) FROM sessions
+        WHERE session_id = :session_id AND revoked_at IS NULL AND expires_at > NOW()";
+
+    let count: Option<u32> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "session_id" => session_id })
+    })
+    .await?;
+
+    Ok(count.unwrap_or_default() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session_id(tokens: &TokenPair) -> String {
+        tokens.refresh_token.split('.').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_create_session() {
+        let tokens = create_session(1, Role::Student).await.unwrap();
+        assert!(is_session_active(session_id(&tokens)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_session_rotates_token() {
+        let tokens = create_session(1, Role::Student).await.unwrap();
+        let refreshed = refresh_session(&tokens.refresh_token).await.unwrap();
+
+        assert_ne!(tokens.refresh_token, refreshed.refresh_token);
+        assert_eq!(session_id(&tokens), session_id(&refreshed));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_session_reuse_revokes() {
+        let tokens = create_session(1, Role::Student).await.unwrap();
+        let _ = refresh_session(&tokens.refresh_token).await.unwrap();
+
+        let result = refresh_session(&tokens.refresh_token).await;
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+        assert!(!is_session_active(session_id(&tokens)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_session_forged_secret_keeps_session() {
+        let tokens = create_session(1, Role::Student).await.unwrap();
+        let forged = format!("{}.garbage", session_id(&tokens));
+
+        let result = refresh_session(&forged).await;
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+        assert!(is_session_active(session_id(&tokens)).await.unwrap());
+        assert!(refresh_session(&tokens.refresh_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_session_invalid() {
+        let result = refresh_session("not-a-token").await;
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn test_revoke_all_sessions() {
+        let first = create_session(1, Role::Student).await.unwrap();
+        let second = create_session(1, Role::Student).await.unwrap();
+
+        revoke_all_sessions(1).await.unwrap();
+
+        assert!(!is_session_active(session_id(&first)).await.unwrap());
+        assert!(!is_session_active(session_id(&second)).await.unwrap());
+    }
+}
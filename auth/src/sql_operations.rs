@@ -1,5 +1,9 @@
+use auth::password::{hash_password, verify_password, Verification};
+use data_access::{AppError, AppResult};
+use log::error;
 use mysql::{params, prelude::Queryable, Row};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 #[derive(Serialize, Deserialize)]
 pub struct User {
@@ -10,17 +14,16 @@ pub struct User {
     pub email: String,
 }
 
-pub async fn login(email: String, password: String) -> Option<User> {
-    let mut conn = data_access::get_connection_safe().ok()?;
-
+pub async fn login(email: String, password: String) -> AppResult<User> {
     let query = "
-        SELECT user_id, user_type_id, name, last_name, email, password
+        SELECT user_id, user_type_id, name, last_name, email, password, email_verified
         FROM users
-        WHERE email = :email AND password = :password";
+        WHERE email = :email";
 
-    let row: Option<Row> = conn
-        .exec_first(query, params! { "email" => email, "password" => password })
-        .ok()?;
+    let row: Option<Row> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "email" => email })
+    })
+    .await?;
 
     if let Some(mut row) = row {
         let user_id: u32 = row.take("user_id").unwrap();
@@ -28,8 +31,23 @@ pub async fn login(email: String, password: String) -> Option<User> {
         let name: String = row.take("name").unwrap();
         let last_name: String = row.take("last_name").unwrap();
         let email: String = row.take("email").unwrap();
+        let stored_password: String = row.take("password").unwrap();
+        let email_verified: bool = row.take("email_verified").unwrap();
+
+        match verify_password(password.clone(), stored_password).await {
+            Verification::Valid => {}
+            Verification::ValidNeedsRehash => upgrade_password_hash(user_id, password).await,
+            Verification::Invalid => return Err(invalid_credentials()),
+        }
+
+        // Checked after the password so the verification state isn't exposed to guessers.
+        if !email_verified {
+            return Err(AppError::Forbidden(
+                "Email address has not been verified.".to_string(),
+            ));
+        }
 
-        return Some(User {
+        return Ok(User {
             user_id,
             user_type_id,
             name,
@@ -38,7 +56,248 @@ pub async fn login(email: String, password: String) -> Option<User> {
         });
     }
 
-    None
+    Err(invalid_credentials())
+}
+
+fn invalid_credentials() -> AppError {
+    AppError::Unauthorized("Invalid email or password.".to_string())
+}
+
+/// Replaces a legacy plaintext or SHA-256 password, or a hash of a non-canonical form, with
+/// an Argon2id hash of the canonical form, so clients sending either form keep working.
+/// Failures are logged and retried on the next login rather than failing this one.
+async fn upgrade_password_hash(user_id: u32, password: String) {
+    let hash = match hash_password(password).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            error!("Failed to hash legacy password for user {}: {}", user_id, e);
+            return;
+        }
+    };
+
+    let query = "UPDATE users SET password = :password WHERE user_id = :user_id";
+
+    let result = data_access::with_connection(move |conn| {
+        conn.exec_drop(query, params! { "password" => hash, "user_id" => user_id })
+    })
+    .await;
+
+    if let Err(e) = result {
+        error!(
+            "Failed to upgrade password hash for user {}: {}",
+            user_id, e
+        );
+    }
+}
+
+/// Reads a numeric limit from the environment, falling back to `default`.
+fn env_limit(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn hash_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+/// Records a verification request, rejecting it if the email or the IP address already
+/// asked for too many codes in the last hour.
+pub async fn throttle_verification_request(email: String, ip: String) -> AppResult<()> {
+    let email_limit = env_limit("VERIFICATION_EMAIL_LIMIT_PER_HOUR", 5);
+    let ip_limit = env_limit("VERIFICATION_IP_LIMIT_PER_HOUR", 20);
+
+    let cleanup_query = "DELETE FROM verification_requests
+        WHERE requested_at < NOW() - INTERVAL 1 HOUR";
+    let count_query = "SELECT
+        COALESCE(SUM(email = :email), 0) AS email_count,
+        COALESCE(SUM(ip_address = :ip_address), 0) AS ip_count
+        FROM verification_requests
+        WHERE requested_at >= NOW() - INTERVAL 1 HOUR";
+    let insert_query = "INSERT INTO verification_requests (email, ip_address, requested_at)
+        VALUES (:email, :ip_address, NOW())";
+
+    let allowed = data_access::with_connection(move |conn| -> AppResult<bool> {
+        conn.query_drop(cleanup_query)?;
+
+        let counts: Option<(u32, u32)> = conn.exec_first(
+            count_query,
+            params! { "email" => &email, "ip_address" => &ip },
+        )?;
+        let (email_count, ip_count) = counts.unwrap_or_default();
+        if email_count >= email_limit || ip_count >= ip_limit {
+            return Ok(false);
+        }
+
+        conn.exec_drop(
+            insert_query,
+            params! { "email" => &email, "ip_address" => &ip },
+        )?;
+        Ok(true)
+    })
+    .await?;
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(AppError::TooManyRequests(
+            "Too many verification requests. Try again later.".to_string(),
+        ))
+    }
+}
+
+/// What a verification code is sent for. A code only works for its own purpose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodePurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl CodePurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CodePurpose::VerifyEmail => "verify_email",
+            CodePurpose::ResetPassword => "reset_password",
+        }
+    }
+}
+
+/// Stores a new code for the email, replacing any previous one for the same purpose and
+/// resetting its attempts.
+pub async fn store_verification_code(
+    email: String,
+    purpose: CodePurpose,
+    code: String,
+) -> AppResult<()> {
+    let ttl_minutes = env_limit("VERIFICATION_CODE_TTL_MINUTES", 10);
+
+    let query = "INSERT INTO verification_codes (email, purpose, code_hash, attempts, expires_at)
+        VALUES (:email, :purpose, :code_hash, 0, NOW() + INTERVAL :ttl_minutes MINUTE)
+        ON DUPLICATE KEY UPDATE
+            code_hash = VALUES(code_hash), attempts = 0, expires_at = VALUES(expires_at)";
+
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(
+            query,
+            params! {
+                "email" => email,
+                "purpose" => purpose.as_str(),
+                "code_hash" => hash_code(&code),
+                "ttl_minutes" => ttl_minutes,
+            },
+        )
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Checks a code and consumes it on success. Every guess counts against
+/// `VERIFICATION_MAX_ATTEMPTS`; once reached, the code is locked until a new one is requested.
+pub async fn check_verification_code(
+    email: String,
+    purpose: CodePurpose,
+    code: String,
+) -> AppResult<()> {
+    let max_attempts = env_limit("VERIFICATION_MAX_ATTEMPTS", 5);
+
+    let attempt_query = "UPDATE verification_codes SET attempts = attempts + 1
+        WHERE email = :email AND purpose = :purpose
+            AND attempts < :max_attempts AND expires_at > NOW()";
+    let state_query = "SELECT code_hash, expires_at > NOW() AS active
+        FROM verification_codes WHERE email = :email AND purpose = :purpose";
+    let consume_query =
+        "DELETE FROM verification_codes WHERE email = :email AND purpose = :purpose";
+
+    let code_hash = hash_code(&code);
+    let purpose = purpose.as_str();
+    data_access::with_connection(move |conn| -> AppResult<()> {
+        let counted = conn
+            .exec_iter(
+                attempt_query,
+                params! {
+                    "email" => &email,
+                    "purpose" => purpose,
+                    "max_attempts" => max_attempts,
+                },
+            )?
+            .affected_rows();
+
+        let state: Option<(String, bool)> = conn.exec_first(
+            state_query,
+            params! { "email" => &email, "purpose" => purpose },
+        )?;
+
+        match state {
+            Some((_, true)) if counted == 0 => Err(AppError::TooManyRequests(
+                "Too many failed attempts. Request a new code.".to_string(),
+            )),
+            Some((stored_hash, true)) if stored_hash == code_hash => {
+                conn.exec_drop(
+                    consume_query,
+                    params! { "email" => &email, "purpose" => purpose },
+                )?;
+                Ok(())
+            }
+            _ => Err(AppError::Unauthorized(
+                "Invalid or expired code.".to_string(),
+            )),
+        }
+    })
+    .await
+}
+
+/// Whether an account exists for the email and still needs to confirm it.
+pub async fn is_pending_verification(email: String) -> AppResult<bool> {
+    let query = "SELECT email_verified FROM users WHERE email = :email";
+
+    let verified: Option<bool> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "email" => email })
+    })
+    .await?;
+
+    match verified {
+        Some(verified) => Ok(!verified),
+        None => Err(AppError::NotFound("User not found.".to_string())),
+    }
+}
+
+/// Marks the account as verified once its code has been accepted.
+pub async fn mark_email_verified(email: String) -> AppResult<()> {
+    let query = "UPDATE users SET email_verified = 1 WHERE email = :email";
+
+    data_access::with_connection(move |conn| conn.exec_drop(query, params! { "email" => email }))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_user_id(email: String) -> AppResult<u32> {
+    let query = "SELECT user_id FROM users WHERE email = :email";
+
+    let user_id: Option<u32> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "email" => email })
+    })
+    .await?;
+
+    user_id.ok_or_else(|| AppError::NotFound("User not found.".to_string()))
+}
+
+/// Replaces the user's password with an Argon2id hash of `password`.
+pub async fn reset_password(user_id: u32, password: String) -> AppResult<()> {
+    let hash = hash_password(password)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to hash password: {}", e)))?;
+
+    let query = "UPDATE users SET password = :password WHERE user_id = :user_id";
+
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(query, params! { "password" => hash, "user_id" => user_id })
+    })
+    .await?;
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -51,7 +310,7 @@ mod tests {
         let password = "123456".to_string();
         let result = login(email, password).await;
 
-        assert!(result.is_some());
+        assert!(result.is_ok());
         let user = result.unwrap();
         assert_eq!(user.email, "juan@uv.mx");
         assert!(!user.name.is_empty());
@@ -66,7 +325,7 @@ mod tests {
         let password = "123456".to_string();
         let result = login(email, password).await;
 
-        assert!(result.is_none());
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
     }
 
     #[tokio::test]
@@ -75,6 +334,117 @@ mod tests {
         let password = "123456".to_string();
         let result = login(email, password).await;
 
-        assert!(result.is_none());
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn test_check_verification_code() {
+        let email = "lizrm@uv.mx".to_string();
+        store_verification_code(
+            email.clone(),
+            CodePurpose::VerifyEmail,
+            "A1B2C3".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let wrong = check_verification_code(
+            email.clone(),
+            CodePurpose::VerifyEmail,
+            "ZZZZZZ".to_string(),
+        )
+        .await;
+        assert!(matches!(wrong, Err(AppError::Unauthorized(_))));
+
+        let result = check_verification_code(
+            email.clone(),
+            CodePurpose::VerifyEmail,
+            "A1B2C3".to_string(),
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let reused =
+            check_verification_code(email, CodePurpose::VerifyEmail, "A1B2C3".to_string()).await;
+        assert!(matches!(reused, Err(AppError::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn test_check_verification_code_locks_after_max_attempts() {
+        let email = "juan@uv.mx".to_string();
+        store_verification_code(
+            email.clone(),
+            CodePurpose::VerifyEmail,
+            "A1B2C3".to_string(),
+        )
+        .await
+        .unwrap();
+
+        for _ in 0..5 {
+            let _ = check_verification_code(
+                email.clone(),
+                CodePurpose::VerifyEmail,
+                "ZZZZZZ".to_string(),
+            )
+            .await;
+        }
+
+        let result =
+            check_verification_code(email, CodePurpose::VerifyEmail, "A1B2C3".to_string()).await;
+        assert!(matches!(result, Err(AppError::TooManyRequests(_))));
+    }
+
+    #[tokio::test]
+    async fn test_check_verification_code_purpose() {
+        let email = "lizrm@uv.mx".to_string();
+        store_verification_code(
+            email.clone(),
+            CodePurpose::ResetPassword,
+            "R3S3T0".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let result = check_verification_code(
+            email.clone(),
+            CodePurpose::VerifyEmail,
+            "R3S3T0".to_string(),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+
+        let result =
+            check_verification_code(email, CodePurpose::ResetPassword, "R3S3T0".to_string()).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_get_user_id() {
+        assert!(get_user_id("lizrm@uv.mx".to_string()).await.unwrap() > 0);
+
+        let result = get_user_id("margaritagh@uv.mx".to_string()).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_throttle_verification_request() {
+        let email = "throttle@uv.mx".to_string();
+        for _ in 0..5 {
+            throttle_verification_request(email.clone(), "10.0.0.1".to_string())
+                .await
+                .unwrap();
+        }
+
+        let result = throttle_verification_request(email, "10.0.0.1".to_string()).await;
+        assert!(matches!(result, Err(AppError::TooManyRequests(_))));
+    }
+
+    #[tokio::test]
+    async fn test_is_pending_verification() {
+        let result = is_pending_verification("lizrm@uv.mx".to_string()).await;
+        assert!(!result.unwrap());
+
+        let result = is_pending_verification("margaritagh@uv.mx".to_string()).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
     }
 }
@@ -11,6 +11,8 @@ pub struct Channel {
     pub description: String,
     #[schema(example = "2", required = true)]
     pub category_id: u32,
+    #[serde(default)]
+    pub visibility: Visibility,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -21,4 +23,25 @@ pub struct ChannelUpdateData {
     pub description: String,
     #[schema(example = "5", required = true)]
     pub category_id: u32,
+    /// Left as it is when omitted.
+    pub visibility: Option<Visibility>,
+}
+
+/// Who can list and download a channel's posts. `members` limits them to the creator and
+/// subscribers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Members,
+}
+
+impl Visibility {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Members => "members",
+        }
+    }
 }
@@ -1,8 +1,10 @@
 use crate::channel;
 use crate::channel::ChannelUpdateData;
+use crate::search::{SearchPage, SearchParams, SearchQuery};
 use crate::sql_operations;
-use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
-use log::error;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use auth::{AuthenticatedUser, Role, RoleGuard};
+use data_access::AppError;
 
 /// Returns all channels stored in database.
 #[utoipa::path(
@@ -12,16 +14,9 @@ use log::error;
     )
 )]
 #[get("/channels/all")]
-pub async fn get_all_channels() -> impl Responder {
-    let result = sql_operations::get_all_channels().await;
-
-    match result {
-        Ok(channels) => HttpResponse::Ok().json(channels),
-        Err(e) => {
-            error!("Failed to fetch all channels: {}", e);
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+pub async fn get_all_channels() -> Result<HttpResponse, AppError> {
+    let channels = sql_operations::get_all_channels().await?;
+    Ok(HttpResponse::Ok().json(channels))
 }
 
 /// Returns a ser of channels where a user is subscribed to.
@@ -32,16 +27,9 @@ pub async fn get_all_channels() -> impl Responder {
     )
 )]
 #[get("/subscriptions/user/{id}")]
-pub async fn get_subscriptions_by_user(user_id: web::Path<u32>) -> impl Responder {
-    let result = sql_operations::get_subscriptions_by_user(*user_id).await;
-
-    match result {
-        Ok(channels) => HttpResponse::Ok().json(channels),
-        Err(e) => {
-            error!("Failed to fetch subscriptions by user: {}", e);
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+pub async fn get_subscriptions_by_user(user_id: web::Path<u32>) -> Result<HttpResponse, AppError> {
+    let channels = sql_operations::get_subscriptions_by_user(*user_id).await?;
+    Ok(HttpResponse::Ok().json(channels))
 }
 
 /// Returns all channels created by an user.
@@ -52,16 +40,11 @@ pub async fn get_subscriptions_by_user(user_id: web::Path<u32>) -> impl Responde
     )
 )]
 #[get("/channels/owner/{id}")]
-pub async fn get_channels_created_by_user(user_id: web::Path<u32>) -> impl Responder {
-    let result = sql_operations::get_channels_created_by_user(*user_id).await;
-
-    match result {
-        Ok(channels) => HttpResponse::Ok().json(channels),
-        Err(e) => {
-            error!("Failed to fetch channels created by user: {}", e);
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+pub async fn get_channels_created_by_user(
+    user_id: web::Path<u32>,
+) -> Result<HttpResponse, AppError> {
+    let channels = sql_operations::get_channels_created_by_user(*user_id).await?;
+    Ok(HttpResponse::Ok().json(channels))
 }
 
 /// Create a channel given the Channel schema.
@@ -69,26 +52,28 @@ pub async fn get_channels_created_by_user(user_id: web::Path<u32>) -> impl Respo
     request_body = Channel,
     responses(
         (status = 200, description = "Channel created succesfully.", body = Channel),
+        (status = 400, description = "Category does not exist."),
+        (status = 403, description = "Only professors can create channels, and only for themselves."),
         (status = 500, description = "Internal server error ocurred."),
     )
 )]
-#[post("/channel/create")]
-pub async fn create_channel(channel: web::Json<channel::Channel>) -> impl Responder {
-    let result = sql_operations::create_channel(
+#[post("/channel/create", wrap = "RoleGuard::allow(&[Role::Professor])")]
+pub async fn create_channel(
+    user: AuthenticatedUser,
+    channel: web::Json<channel::Channel>,
+) -> Result<HttpResponse, AppError> {
+    user.ensure_owner(channel.creator_id)?;
+
+    sql_operations::create_channel(
         channel.creator_id,
         channel.name.clone(),
         channel.description.clone(),
         channel.category_id,
+        channel.visibility,
     )
-    .await;
-
-    match result {
-        Ok(_) => HttpResponse::Ok().json("Channel created successfully."),
-        Err(e) => {
-            error!("Failed to create channel: {}", e);
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    .await?;
+
+    Ok(HttpResponse::Ok().json("Channel created successfully."))
 }
 
 /// Update a channel given the ChannelUpdateData schema.
@@ -96,50 +81,51 @@ pub async fn create_channel(channel: web::Json<channel::Channel>) -> impl Respon
     request_body = ChannelUpdateData,
     responses(
         (status = 200, description = "Channel updated successfully.", body = ChannelUpdateData),
+        (status = 403, description = "Only the channel creator can update it."),
+        (status = 404, description = "Channel not found."),
         (status = 500, description = "Internal server error ocurred."),
     )
 )]
 #[put("/channel/update/{id}")]
 pub async fn update_channel(
+    user: AuthenticatedUser,
     channel_id: web::Path<u32>,
     channel_data: web::Json<ChannelUpdateData>,
-) -> impl Responder {
-    let result = sql_operations::update_channel(
+) -> Result<HttpResponse, AppError> {
+    let creator_id = sql_operations::get_creator_id(*channel_id).await?;
+    user.ensure_owner(creator_id)?;
+
+    sql_operations::update_channel(
         *channel_id,
         channel_data.name.clone(),
         channel_data.description.clone(),
         channel_data.category_id,
+        channel_data.visibility,
     )
-    .await;
-
-    match result {
-        Ok(_) => HttpResponse::Ok().json("Channel updated successfully."),
-        Err(e) => {
-            error!("Failed to update channel: {}", e);
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    .await?;
+
+    Ok(HttpResponse::Ok().json("Channel updated successfully."))
 }
 
 /// Delete a channel by ID.
 #[utoipa::path(
     responses(
         (status = 200, description = "Channel deleted successfully."),
+        (status = 403, description = "Only the channel creator can delete it."),
         (status = 404, description = "Channel not found."),
         (status = 500, description = "Internal server error occurred.")
     )
 )]
 #[delete("/channel/delete/{id}")]
-pub async fn delete_channel(channel_id: web::Path<u32>) -> impl Responder {
-    let result = sql_operations::delete_channel(*channel_id).await;
-
-    match result {
-        Ok(_) => HttpResponse::Ok().json("Channel deleted successfully."),
-        Err(e) => {
-            error!("Failed to delete channel: {}", e);
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+pub async fn delete_channel(
+    user: AuthenticatedUser,
+    channel_id: web::Path<u32>,
+) -> Result<HttpResponse, AppError> {
+    let creator_id = sql_operations::get_creator_id(*channel_id).await?;
+    user.ensure_owner(creator_id)?;
+
+    sql_operations::delete_channel(*channel_id).await?;
+    Ok(HttpResponse::Ok().json("Channel deleted successfully."))
 }
 
 /// Returns all channel categories.
@@ -150,16 +136,9 @@ pub async fn delete_channel(channel_id: web::Path<u32>) -> impl Responder {
     )
 )]
 #[get("/categories/all")]
-pub async fn get_all_categories() -> impl Responder {
-    let result = sql_operations::get_all_categories().await;
-
-    match result {
-        Ok(categories) => HttpResponse::Ok().json(categories),
-        Err(e) => {
-            error!("Failed to fetch all categories: {}", e);
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+pub async fn get_all_categories() -> Result<HttpResponse, AppError> {
+    let categories = sql_operations::get_all_categories().await?;
+    Ok(HttpResponse::Ok().json(categories))
 }
 
 /// Returns the name of a channel by ID.
@@ -171,10 +150,10 @@ pub async fn get_all_categories() -> impl Responder {
     )
 )]
 #[get("/channel/name/{id}")]
-pub async fn get_channel_name_by_id(path: web::Path<u32>) -> impl Responder {
+pub async fn get_channel_name_by_id(path: web::Path<u32>) -> Result<HttpResponse, AppError> {
     let channel_id = path.into_inner();
-    let channel_name = sql_operations::get_channel_name(channel_id).await;
-    HttpResponse::Ok().json(channel_name)
+    let channel_name = sql_operations::get_channel_name(channel_id).await?;
+    Ok(HttpResponse::Ok().json(channel_name))
 }
 
 /// Returns the creator ID of a channel by channel ID.
@@ -186,8 +165,34 @@ pub async fn get_channel_name_by_id(path: web::Path<u32>) -> impl Responder {
     )
 )]
 #[get("/creator/channel/{id}")]
-pub async fn get_creator_id_by_channel_id(path: web::Path<u32>) -> impl Responder {
+pub async fn get_creator_id_by_channel_id(path: web::Path<u32>) -> Result<HttpResponse, AppError> {
     let channel_id = path.into_inner();
-    let creator_id = sql_operations::get_creator_id(channel_id).await;
-    HttpResponse::Ok().json(creator_id)
+    let creator_id = sql_operations::get_creator_id(channel_id).await?;
+    Ok(HttpResponse::Ok().json(creator_id))
+}
+
+/// Searches channels, posts and comments, best matches first.
+#[utoipa::path(
+    params(SearchParams),
+    responses(
+        (status = 200, description = "Returns a page of search results.", body = SearchPage),
+        (status = 400, description = "Invalid search parameters."),
+        (status = 500, description = "Internal server error occurred.")
+    )
+)]
+#[get("/search")]
+pub async fn search(
+    user: AuthenticatedUser,
+    params: web::Query<SearchParams>,
+) -> Result<HttpResponse, AppError> {
+    let query = SearchQuery::from_params(&params)?;
+    let (page, page_size) = (query.page, query.page_size);
+
+    let (results, has_more) = sql_operations::search(query, &user).await?;
+    Ok(HttpResponse::Ok().json(SearchPage {
+        results,
+        page,
+        page_size,
+        has_more,
+    }))
 }
@@ -1,5 +1,6 @@
 mod channel;
 mod controller;
+mod search;
 mod sql_operations;
 
 use actix_cors::Cors;
@@ -11,6 +12,8 @@ use auth::validate_jwt;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    data_access::get_pool().expect("Failed to create database pool");
+
     HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
@@ -29,14 +32,25 @@ async fn main() -> std::io::Result<()> {
                 controller::delete_channel,
                 controller::get_channel_name_by_id,
                 controller::get_creator_id_by_channel_id,
+                controller::search,
             ),
-            components(schemas(channel::Channel, channel::ChannelUpdateData,))
+            components(schemas(
+                channel::Channel,
+                channel::ChannelUpdateData,
+                channel::Visibility,
+                search::SearchPage,
+                search::SearchResult,
+                search::ResultType
+            ))
         )]
         struct ApiDoc;
 
         let openapi = ApiDoc::openapi();
 
         App::new()
+            .app_data(data_access::error::json_config())
+            .app_data(data_access::error::path_config())
+            .app_data(data_access::error::query_config())
             .wrap(HttpAuthentication::bearer(validate_jwt))
             .wrap(cors)
             .service(
@@ -51,6 +65,7 @@ async fn main() -> std::io::Result<()> {
             .service(controller::delete_channel)
             .service(controller::get_channel_name_by_id)
             .service(controller::get_creator_id_by_channel_id)
+            .service(controller::search)
     })
     .bind("0.0.0.0:8080")?
     .run()
@@ -0,0 +1,284 @@
+use data_access::{AppError, AppResult};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+const MAX_QUERY_LENGTH: usize = 100;
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 50;
+
+/// Characters of a result's text shown in its snippet, and how many of them come before
+/// the first match.
+const SNIPPET_LENGTH: usize = 160;
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Characters of a file's extracted text that are loaded for its snippet, and how many of
+/// them come before the first search word. The context is longer than the snippet's, so a
+/// snippet of a cut excerpt always starts with an ellipsis.
+pub const EXCERPT_LENGTH: usize = 1000;
+pub const EXCERPT_CONTEXT: usize = 200;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    /// Words to search for.
+    #[param(example = "software design")]
+    pub q: String,
+    /// Comma separated result types to include; all of them by default.
+    #[param(example = "channel,post")]
+    pub types: Option<String>,
+    /// Page number, starting at 1.
+    pub page: Option<u32>,
+    /// Results per page, 20 by default and at most 50.
+    pub page_size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultType {
+    Channel,
+    Post,
+    Comment,
+}
+
+impl ResultType {
+    pub const ALL: [ResultType; 3] = [ResultType::Channel, ResultType::Post, ResultType::Comment];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResultType::Channel => "channel",
+            ResultType::Post => "post",
+            ResultType::Comment => "comment",
+        }
+    }
+
+    pub fn parse(value: &str) -> AppResult<Self> {
+        ResultType::ALL
+            .into_iter()
+            .find(|result_type| result_type.as_str() == value)
+            .ok_or_else(|| AppError::Validation(format!("Unknown result type \"{}\".", value)))
+    }
+}
+
+/// A match, ranked by `score`. `id` is the id of the channel, post or comment; comments
+/// are titled with their post's title. Posts also match the text of their file, which the
+/// snippet then comes from. `title` and `snippet` are HTML-escaped, with the matched words
+/// wrapped in `<mark>`.
+#[derive(Serialize, ToSchema)]
+pub struct SearchResult {
+    #[serde(rename = "type")]
+    pub result_type: ResultType,
+    #[schema(example = "12")]
+    pub id: u32,
+    #[schema(example = "3")]
+    pub channel_id: u32,
+    pub post_id: Option<u32>,
+    #[schema(example = "<mark>Software</mark> Design")]
+    pub title: String,
+    #[schema(example = "Notes on <mark>software</mark> architecture…")]
+    pub snippet: String,
+    pub score: f64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    pub page: u32,
+    pub page_size: u32,
+    pub has_more: bool,
+}
+
+/// A validated search.
+pub struct SearchQuery {
+    pub text: String,
+    pub types: Vec<ResultType>,
+    pub page: u32,
+    pub page_size: u32,
+}
+
+impl SearchQuery {
+    pub fn from_params(params: &SearchParams) -> AppResult<Self> {
+        let text = params.q.trim().to_string();
+        if text.is_empty() || text.chars().count() > MAX_QUERY_LENGTH {
+            return Err(AppError::Validation(format!(
+                "The search must be between 1 and {} characters.",
+                MAX_QUERY_LENGTH
+            )));
+        }
+
+        let types = match params.types.as_deref().map(str::trim) {
+            None | Some("") => ResultType::ALL.to_vec(),
+            Some(types) => {
+                let mut unique = Vec::new();
+                for result_type in types.split(',') {
+                    let result_type = ResultType::parse(result_type.trim())?;
+                    if !unique.contains(&result_type) {
+                        unique.push(result_type);
+                    }
+                }
+                unique
+            }
+        };
+
+        Ok(SearchQuery {
+            text,
+            types,
+            page: params.page.unwrap_or(1).max(1),
+            page_size: params
+                .page_size
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .clamp(1, MAX_PAGE_SIZE),
+        })
+    }
+
+    pub fn offset(&self) -> u64 {
+        u64::from(self.page - 1) * u64::from(self.page_size)
+    }
+
+    /// The lowercased words of the search, for highlighting.
+    pub fn terms(&self) -> Vec<String> {
+        words(&self.text)
+            .map(|(start, end)| self.text[start..end].to_lowercase())
+            .collect()
+    }
+}
+
+/// Byte ranges of the words (runs of alphanumeric characters) in `text`.
+fn words(text: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut chars = text.char_indices().peekable();
+    std::iter::from_fn(move || {
+        let (start, _) = chars.find(|(_, c)| c.is_alphanumeric())?;
+        let mut end = text.len();
+        while let Some(&(index, c)) = chars.peek() {
+            if !c.is_alphanumeric() {
+                end = index;
+                break;
+            }
+            chars.next();
+        }
+        Some((start, end))
+    })
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// HTML-escapes `text`, wrapping the words that match one of `terms` in `<mark>`. Text
+/// longer than a snippet is cut around the first match.
+pub fn highlight(text: &str, terms: &[String]) -> String {
+    let matches: Vec<(usize, usize)> = words(text)
+        .filter(|&(start, end)| terms.contains(&text[start..end].to_lowercase()))
+        .collect();
+
+    let char_starts: Vec<usize> = text.char_indices().map(|(index, _)| index).collect();
+    let (start, end) = if char_starts.len() <= SNIPPET_LENGTH {
+        (0, text.len())
+    } else {
+        let anchor = matches
+            .first()
+            .map(|&(start, _)| char_starts.partition_point(|&index| index < start))
+            .unwrap_or(0);
+        let first = anchor
+            .saturating_sub(SNIPPET_CONTEXT)
+            .min(char_starts.len() - SNIPPET_LENGTH);
+        let last = first + SNIPPET_LENGTH;
+        (
+            char_starts[first],
+            char_starts.get(last).copied().unwrap_or(text.len()),
+        )
+    };
+
+    let mut out = String::new();
+    if start > 0 {
+        out.push('…');
+    }
+    let mut position = start;
+    for (match_start, match_end) in matches {
+        if match_start < start || match_end > end {
+            continue;
+        }
+        escape_html(&text[position..match_start], &mut out);
+        out.push_str("<mark>");
+        escape_html(&text[match_start..match_end], &mut out);
+        out.push_str("</mark>");
+        position = match_end;
+    }
+    escape_html(&text[position..end], &mut out);
+    if end < text.len() {
+        out.push('…');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(q: &str, types: Option<&str>) -> SearchParams {
+        SearchParams {
+            q: q.to_string(),
+            types: types.map(str::to_string),
+            page: None,
+            page_size: Some(500),
+        }
+    }
+
+    #[test]
+    fn test_search_query() {
+        let query = SearchQuery::from_params(&params(" Software  design ", None)).unwrap();
+        assert_eq!(query.text, "Software  design");
+        assert_eq!(query.types, ResultType::ALL.to_vec());
+        assert_eq!(query.page, 1);
+        assert_eq!(query.page_size, 50);
+        assert_eq!(query.terms(), vec!["software", "design"]);
+
+        let query = SearchQuery::from_params(&params("design", Some("post, comment"))).unwrap();
+        assert_eq!(query.types, vec![ResultType::Post, ResultType::Comment]);
+
+        let query = SearchQuery::from_params(&params("design", Some("post,comment,post"))).unwrap();
+        assert_eq!(query.types, vec![ResultType::Post, ResultType::Comment]);
+
+        let result = SearchQuery::from_params(&params("design", Some("users")));
+        assert!(matches!(result, Err(AppError::Validation(_))));
+
+        let result = SearchQuery::from_params(&params("  ", None));
+        assert!(matches!(result, Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn test_highlight() {
+        let terms = vec!["design".to_string()];
+        assert_eq!(
+            highlight("Software <Design> & designs", &terms),
+            "Software &lt;<mark>Design</mark>&gt; &amp; designs"
+        );
+    }
+
+    #[test]
+    fn test_highlight_snippet() {
+        let terms = vec!["árbol".to_string()];
+        let text = format!("{} Árbol {}", "a".repeat(200), "b".repeat(200));
+        let snippet = highlight(&text, &terms);
+
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains("<mark>Árbol</mark>"));
+        assert_eq!(
+            snippet
+                .replace("<mark>", "")
+                .replace("</mark>", "")
+                .chars()
+                .count(),
+            SNIPPET_LENGTH + 2
+        );
+    }
+}
@@ -1,3 +1,7 @@
+use crate::channel::Visibility;
+use crate::search::{self, ResultType, SearchQuery, SearchResult};
+use auth::{AuthenticatedUser, Role};
+use data_access::{AppError, AppResult};
 use mysql::{params, prelude::Queryable, Row};
 use serde::{Deserialize, Serialize};
 
@@ -10,82 +14,60 @@ pub struct Channel {
     name: String,
     description: String,
     category_name: String,
+    visibility: String,
 }
 
-pub async fn get_all_channels() -> Result<Vec<Channel>, mysql::Error> {
-    let mut conn = data_access::get_connection_safe()?;
+fn channel_from_row(mut row: Row) -> Channel {
+    Channel {
+        channel_id: row.take("channel_id").unwrap(),
+        creator_id: row.take("creator_id").unwrap(),
+        creator_name: row.take("creator_name").unwrap(),
+        creator_last_name: row.take("creator_last_name").unwrap(),
+        name: row.take("name").unwrap(),
+        description: row.take("description").unwrap(),
+        category_name: row.take("category_name").unwrap(),
+        visibility: row.take("visibility").unwrap(),
+    }
+}
+
+pub async fn get_all_channels() -> AppResult<Vec<Channel>> {
     let query =
         "SELECT channels.*, users.name as creator_name, users.last_name as creator_last_name, categories.name as category_name
         FROM channels INNER JOIN users ON channels.creator_id = users.user_id
         INNER JOIN categories ON channels.category_id = categories.category_id";
-    let mut channels: Vec<Channel> = Vec::new();
-    conn.query_map(query, |mut row: Row| {
-        let channel = Channel {
-            channel_id: row.take("channel_id").unwrap(),
-            creator_id: row.take("creator_id").unwrap(),
-            creator_name: row.take("creator_name").unwrap(),
-            creator_last_name: row.take("creator_last_name").unwrap(),
-            name: row.take("name").unwrap(),
-            description: row.take("description").unwrap(),
-            category_name: row.take("category_name").unwrap(),
-        };
 
-        channels.push(channel);
-    })?;
+    let channels =
+        data_access::with_connection(move |conn| conn.query_map(query, channel_from_row)).await?;
 
     Ok(channels)
 }
 
-pub async fn get_subscriptions_by_user(user_id: u32) -> Result<Vec<Channel>, mysql::Error> {
-    let mut conn = data_access::get_connection_safe()?;
+pub async fn get_subscriptions_by_user(user_id: u32) -> AppResult<Vec<Channel>> {
     let query =
         "SELECT channels.*, users.name as creator_name, users.last_name as creator_last_name, categories.name as category_name
         FROM channels INNER JOIN users ON channels.creator_id = users.user_id
         INNER JOIN categories ON channels.category_id = categories.category_id
         WHERE channels.channel_id IN (SELECT channel_id FROM subscriptions WHERE user_id = :user_id)";
-    let mut channels: Vec<Channel> = Vec::new();
-
-    conn.exec_map(query, params! { "user_id" => user_id }, |mut row: Row| {
-        let channel = Channel {
-            channel_id: row.take("channel_id").unwrap(),
-            creator_id: row.take("creator_id").unwrap(),
-            creator_name: row.take("creator_name").unwrap(),
-            creator_last_name: row.take("creator_last_name").unwrap(),
-            name: row.take("name").unwrap(),
-            description: row.take("description").unwrap(),
-            category_name: row.take("category_name").unwrap(),
-        };
-        channels.push(channel);
-    })?;
+
+    let channels = data_access::with_connection(move |conn| {
+        conn.exec_map(query, params! { "user_id" => user_id }, channel_from_row)
+    })
+    .await?;
 
     Ok(channels)
 }
 
-pub async fn get_channels_created_by_user(user_id: u32) -> Result<Vec<Channel>, mysql::Error> {
-    let mut conn = data_access::get_connection_safe()?;
+pub async fn get_channels_created_by_user(user_id: u32) -> AppResult<Vec<Channel>> {
     let query =
         "SELECT channels.*, users.name as creator_name, users.last_name as creator_last_name, categories.name as category_name
         FROM channels INNER JOIN users ON channels.creator_id = users.user_id
         INNER JOIN categories ON channels.category_id = categories.category_id
         WHERE channels.creator_id = :creator_id";
 
-    let mut channels: Vec<Channel> = Vec::new();
-    conn.exec_map(
-        query,
-        params! { "creator_id" => user_id },
-        |mut row: Row| {
-            let channel = Channel {
-                channel_id: row.take("channel_id").unwrap(),
-                creator_id: row.take("creator_id").unwrap(),
-                creator_name: row.take("creator_name").unwrap(),
-                creator_last_name: row.take("creator_last_name").unwrap(),
-                name: row.take("name").unwrap(),
-                description: row.take("description").unwrap(),
-                category_name: row.take("category_name").unwrap(),
-            };
-            channels.push(channel);
-        },
-    )?;
+    let channels = data_access::with_connection(move |conn| {
+        conn.exec_map(query, params! { "creator_id" => user_id }, channel_from_row)
+    })
+    .await?;
 
     Ok(channels)
 }
@@ -95,22 +77,26 @@ pub async fn create_channel(
     name: String,
     description: String,
     category_id: u32,
-) -> Result<bool, mysql::Error> {
-    let mut conn = data_access::get_connection_safe()?;
-    let query = "INSERT INTO channels (creator_id, name, description, category_id)
-        VALUES (:creator_id, :name, :description, :category_id)";
-
-    let result = conn.exec_iter(
-        query,
-        params! {
-            "creator_id" => creator_id,
-            "name" => name,
-            "description" => description,
-            "category_id" => category_id,
-        },
-    )?;
-
-    Ok(result.affected_rows() == 1)
+    visibility: Visibility,
+) -> AppResult<()> {
+    let query = "INSERT INTO channels (creator_id, name, description, category_id, visibility)
+        VALUES (:creator_id, :name, :description, :category_id, :visibility)";
+
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(
+            query,
+            params! {
+                "creator_id" => creator_id,
+                "name" => name,
+                "description" => description,
+                "category_id" => category_id,
+                "visibility" => visibility.as_str(),
+            },
+        )
+    })
+    .await?;
+
+    Ok(())
 }
 
 pub async fn update_channel(
@@ -118,38 +104,54 @@ pub async fn update_channel(
     name: String,
     description: String,
     category_id: u32,
-) -> Result<bool, mysql::Error> {
-    let mut conn = data_access::get_connection_safe()?;
-    let query =
-        "UPDATE channels SET name = :name, description = :description, category_id = :category_id
+    visibility: Option<Visibility>,
+) -> AppResult<()> {
+    let query = "UPDATE channels
+        SET name = :name, description = :description, category_id = :category_id,
+            visibility = COALESCE(:visibility, visibility)
         WHERE channel_id = :channel_id";
 
-    let result = conn.exec_iter(
-        query,
-        params! {
-            "name" => name,
-            "description" => description,
-            "category_id" => category_id,
-            "channel_id" => channel_id,
-        },
-    )?;
-
-    Ok(result.affected_rows() > 0)
-}
+    let affected_rows = data_access::with_connection(move |conn| {
+        conn.exec_iter(
+            query,
+            params! {
+                "name" => name,
+                "description" => description,
+                "category_id" => category_id,
+                "visibility" => visibility.map(Visibility::as_str),
+                "channel_id" => channel_id,
+            },
+        )
+        .map(|result| result.affected_rows())
+    })
+    .await?;
+
+    if affected_rows == 0 {
+        return Err(AppError::NotFound("Channel not found.".to_string()));
+    }
 
-pub async fn delete_channel(channel_id: u32) -> Result<bool, mysql::Error> {
-    let mut conn = data_access::get_connection_safe()?;
+    Ok(())
+}
 
+pub async fn delete_channel(channel_id: u32) -> AppResult<()> {
     let query = "DELETE FROM channels WHERE channel_id = :channel_id";
 
-    let result = conn.exec_iter(
-        query,
-        params! {
-            "channel_id" => channel_id
-        },
-    )?;
+    let affected_rows = data_access::with_connection(move |conn| {
+        conn.exec_iter(
+            query,
+            params! {
+                "channel_id" => channel_id
+            },
+        )
+        .map(|result| result.affected_rows())
+    })
+    .await?;
+
+    if affected_rows == 0 {
+        return Err(AppError::NotFound("Channel not found.".to_string()));
+    }
 
-    Ok(result.affected_rows() == 1)
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize)]
@@ -158,57 +160,159 @@ pub struct Category {
     name: String,
 }
 
-pub async fn get_all_categories() -> Result<Vec<Category>, mysql::Error> {
-    let mut conn = data_access::get_connection_safe()?;
+pub async fn get_all_categories() -> AppResult<Vec<Category>> {
     let query = "SELECT category_id, name FROM categories";
-    let mut categories: Vec<Category> = Vec::new();
 
-    conn.query_map(query, |mut row: Row| {
-        let category = Category {
+    let categories = data_access::with_connection(move |conn| {
+        conn.query_map(query, |mut row: Row| Category {
             category_id: row.take("category_id").unwrap(),
             name: row.take("name").unwrap(),
-        };
-        categories.push(category);
-    })?;
+        })
+    })
+    .await?;
 
     Ok(categories)
 }
 
-pub async fn get_channel_name(channel_id: u32) -> String {
-    let mut conn = data_access::get_connection_safe().expect("could not get connection");
-
+pub async fn get_channel_name(channel_id: u32) -> AppResult<String> {
     let query = "SELECT name FROM channels WHERE channel_id = :channel_id";
 
-    let result: Vec<String> = conn
-        .exec(
+    let result: Option<String> = data_access::with_connection(move |conn| {
+        conn.exec_first(
             query,
             params! {
                 "channel_id" => channel_id
             },
         )
-        .expect("Failed to execute query");
+    })
+    .await?;
 
-    result
-        .into_iter()
-        .next()
-        .unwrap_or_else(|| "Unknown Channel".to_string())
+    result.ok_or_else(|| AppError::NotFound("Channel not found.".to_string()))
 }
 
-pub async fn get_creator_id(channel_id: u32) -> Option<u32> {
-    let mut conn = data_access::get_connection_safe().expect("could not get connection");
-
+pub async fn get_creator_id(channel_id: u32) -> AppResult<u32> {
     let query = "SELECT creator_id FROM channels WHERE channel_id = :channel_id";
 
-    let result: Vec<u32> = conn
-        .exec(
+    let result: Option<u32> = data_access::with_connection(move |conn| {
+        conn.exec_first(
             query,
             params! {
                 "channel_id" => channel_id
             },
         )
-        .expect("Failed to execute query");
+    })
+    .await?;
+
+    result.ok_or_else(|| AppError::NotFound("Channel not found.".to_string()))
+}
+
+/// Posts and comments of members-only channels are only found by their creator, their
+/// subscribers and administrators.
+const VISIBLE_CHANNEL: &str = "(channels.visibility = 'public'
+    OR channels.creator_id = :user_id OR :is_admin
+    OR EXISTS(SELECT 1 FROM subscriptions
+        WHERE subscriptions.channel_id = channels.channel_id
+        AND subscriptions.user_id = :user_id))";
+
+fn search_select(result_type: ResultType) -> String {
+    match result_type {
+        ResultType::Channel => "SELECT 'channel' AS type, channels.channel_id AS id,
+                channels.channel_id, NULL AS post_id, channels.name AS title,
+                COALESCE(channels.description, '') AS body,
+                MATCH(channels.name, channels.description)
+                    AGAINST (:text IN NATURAL LANGUAGE MODE) AS score
+            FROM channels
+            WHERE MATCH(channels.name, channels.description)
+                AGAINST (:text IN NATURAL LANGUAGE MODE)"
+            .to_string(),
+        // Posts also match the text extracted from their file by the posts service, an
+        // excerpt of which is shown when the title and description don't match. The excerpt
+        // starts shortly before the first search word.
+        ResultType::Post => format!(
+            "SELECT 'post' AS type, posts.post_id AS id, posts.channel_id,
+                posts.post_id, posts.title,
+                IF(MATCH(posts.title, posts.description)
+                    AGAINST (:text IN NATURAL LANGUAGE MODE) > 0,
+                    COALESCE(posts.description, ''),
+                    SUBSTRING(blob_texts.content,
+                        GREATEST(1, LOCATE(:term, blob_texts.content) - {}), {})) AS body,
+                MATCH(posts.title, posts.description)
+                    AGAINST (:text IN NATURAL LANGUAGE MODE)
+                + COALESCE(MATCH(blob_texts.content)
+                    AGAINST (:text IN NATURAL LANGUAGE MODE), 0) AS score
+            FROM posts INNER JOIN channels ON channels.channel_id = posts.channel_id
+            INNER JOIN files ON files.file_id = posts.file_id
+            LEFT JOIN blob_texts ON blob_texts.sha256 = files.sha256
+            WHERE (MATCH(posts.title, posts.description)
+                AGAINST (:text IN NATURAL LANGUAGE MODE)
+                OR MATCH(blob_texts.content) AGAINST (:text IN NATURAL LANGUAGE MODE))
+            AND {}",
+            search::EXCERPT_CONTEXT,
+            search::EXCERPT_LENGTH,
+            VISIBLE_CHANNEL
+        ),
+        ResultType::Comment => format!(
+            "SELECT 'comment' AS type, comments.comment_id AS id, posts.channel_id,
+                posts.post_id, posts.title, comments.comment AS body,
+                MATCH(comments.comment) AGAINST (:text IN NATURAL LANGUAGE MODE) AS score
+            FROM comments INNER JOIN posts ON posts.post_id = comments.post_id
+            INNER JOIN channels ON channels.channel_id = posts.channel_id
+            WHERE MATCH(comments.comment) AGAINST (:text IN NATURAL LANGUAGE MODE) AND {}",
+            VISIBLE_CHANNEL
+        ),
+    }
+}
+
+/// Ranked matches of the search the user is allowed to see. Returns one page, and whether
+/// there are more.
+pub async fn search(
+    query: SearchQuery,
+    user: &AuthenticatedUser,
+) -> AppResult<(Vec<SearchResult>, bool)> {
+    let selects: Vec<String> = query.types.iter().map(|t| search_select(*t)).collect();
+    let sql = format!(
+        "SELECT * FROM ({}) AS results ORDER BY score DESC, type, id LIMIT :limit OFFSET :offset",
+        selects.join(" UNION ALL ")
+    );
+    let terms = query.terms();
+    let first_term = terms.first().cloned().unwrap_or_default();
+    let page_size = query.page_size as usize;
+    let (user_id, is_admin) = (user.user_id, user.role == Role::Administrator);
+
+    let mut results = data_access::with_connection(move |conn| {
+        conn.exec_map(
+            sql,
+            params! {
+                "text" => &query.text,
+                "term" => first_term,
+                "user_id" => user_id,
+                "is_admin" => is_admin,
+                "limit" => query.page_size + 1,
+                "offset" => query.offset(),
+            },
+            |mut row: Row| {
+                let result_type: String = row.take("type").unwrap();
+                let title: String = row.take("title").unwrap();
+                let body: String = row.take("body").unwrap();
+
+                SearchResult {
+                    result_type: ResultType::parse(&result_type).unwrap(),
+                    id: row.take("id").unwrap(),
+                    channel_id: row.take("channel_id").unwrap(),
+                    post_id: row.take("post_id").unwrap(),
+                    title: search::highlight(&title, &terms),
+                    snippet: search::highlight(&body, &terms),
+                    score: row.take("score").unwrap(),
+                }
+            },
+        )
+    })
+    .await?;
+
+    let has_more = results.len() > page_size;
+    results.truncate(page_size);
 
-    result.into_iter().next()
+    Ok((results, has_more))
 }
 
 #[cfg(test)]
@@ -246,9 +350,10 @@ mod tests {
             "Test Channel".to_string(),
             "A test description".to_string(),
             1,
+            Visibility::Public,
         )
         .await;
-        assert!(result.unwrap(), "Failed to create channel");
+        assert!(result.is_ok(), "Failed to create channel");
     }
 
     #[tokio::test]
@@ -258,9 +363,13 @@ mod tests {
             "Test Channel".to_string(),
             "A test description".to_string(),
             9999,
+            Visibility::Public,
         )
         .await;
-        assert!(result.is_err(), "Channel created with invalid category");
+        assert!(
+            matches!(result, Err(AppError::Validation(_))),
+            "Channel created with invalid category"
+        );
     }
 
     #[tokio::test]
@@ -270,9 +379,10 @@ mod tests {
             "Updated Channel".to_string(),
             "Updated description".to_string(),
             2,
+            Some(Visibility::Public),
         )
         .await;
-        assert!(result.unwrap(), "Failed to update channel");
+        assert!(result.is_ok(), "Failed to update channel");
     }
 
     #[tokio::test]
@@ -282,21 +392,28 @@ mod tests {
             "Updated Channel".to_string(),
             "Updated description".to_string(),
             2,
+            None,
         )
         .await;
-        assert!(!result.unwrap(), "Updated channel with invalid ID");
+        assert!(
+            matches!(result, Err(AppError::NotFound(_))),
+            "Updated channel with invalid ID"
+        );
     }
 
     #[tokio::test]
     async fn test_delete_channel_success() {
         let result = delete_channel(1).await;
-        assert!(result.unwrap(), "Failed to delete channel");
+        assert!(result.is_ok(), "Failed to delete channel");
     }
 
     #[tokio::test]
     async fn test_delete_channel_invalid_id() {
         let result = delete_channel(9999).await;
-        assert!(!result.unwrap(), "Deleted channel with invalid ID");
+        assert!(
+            matches!(result, Err(AppError::NotFound(_))),
+            "Deleted channel with invalid ID"
+        );
     }
 
     #[tokio::test]
@@ -308,7 +425,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_channel_name() {
-        let result = get_channel_name(1).await;
+        let result = get_channel_name(1).await.unwrap();
         assert!(!result.is_empty());
     }
 
@@ -321,12 +438,48 @@ mod tests {
     #[tokio::test]
     async fn test_get_channel_name_invalid() {
         let result = get_channel_name(0).await;
-        assert!(!result.is_empty());
+        assert!(matches!(result, Err(AppError::NotFound(_))));
     }
 
     #[tokio::test]
     async fn test_get_creator_id_incalid() {
         let result = get_creator_id(0).await;
-        assert!(result.unwrap() != 0);
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+
+    fn search_query(text: &str) -> SearchQuery {
+        SearchQuery::from_params(&search::SearchParams {
+            q: text.to_string(),
+            types: None,
+            page: None,
+            page_size: None,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_search_channels() {
+        let user = AuthenticatedUser {
+            user_id: 1,
+            role: Role::Student,
+        };
+        let (results, _) = search(search_query("arquitectura"), &user).await.unwrap();
+
+        let first = results.first().unwrap();
+        assert_eq!(first.result_type, ResultType::Channel);
+        assert_eq!(first.id, 2);
+        assert!(first.title.contains("<mark>Arquitectura</mark>"));
+    }
+
+    #[tokio::test]
+    async fn test_search_no_results() {
+        let user = AuthenticatedUser {
+            user_id: 1,
+            role: Role::Student,
+        };
+        let (results, has_more) = search(search_query("zzzyyyxxx"), &user).await.unwrap();
+
+        assert!(results.is_empty());
+        assert!(!has_more);
     }
 }
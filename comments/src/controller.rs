@@ -1,25 +1,28 @@
 use crate::comment::CommentToInsert;
 use crate::{comment::CommentToUpdate, sql_operations};
-use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
-use log::error;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use auth::AuthenticatedUser;
+use data_access::AppError;
 
 /// Create a new comment.
 #[utoipa::path(
     request_body = CommentToInsert,
     responses(
         (status = 200, description = "Comment created successfully."),
+        (status = 400, description = "Post or user does not exist, or rating is out of range."),
+        (status = 403, description = "Comments can only be posted as the caller."),
         (status = 500, description = "Internal server error occurred.")
     )
 )]
 #[post("/comment")]
-pub async fn comment_post(data: web::Json<CommentToInsert>) -> impl Responder {
-    match sql_operations::comment(data.into_inner()).await {
-        true => HttpResponse::Ok().json("Comment created successfully."),
-        false => {
-            error!("Failed to create comment");
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+pub async fn comment_post(
+    user: AuthenticatedUser,
+    data: web::Json<CommentToInsert>,
+) -> Result<HttpResponse, AppError> {
+    user.ensure_owner(data.user_id)?;
+
+    sql_operations::comment(data.into_inner()).await?;
+    Ok(HttpResponse::Ok().json("Comment created successfully."))
 }
 
 /// Retrieve all comments by post ID.
@@ -30,10 +33,10 @@ pub async fn comment_post(data: web::Json<CommentToInsert>) -> impl Responder {
     )
 )]
 #[get("/comment/all/{id}")]
-pub async fn get_all_comments_by_post_id(path: web::Path<u32>) -> impl Responder {
+pub async fn get_all_comments_by_post_id(path: web::Path<u32>) -> Result<HttpResponse, AppError> {
     let post_id = path.into_inner();
-    let comments = sql_operations::get_all_comments(post_id).await;
-    HttpResponse::Ok().json(comments)
+    let comments = sql_operations::get_all_comments(post_id).await?;
+    Ok(HttpResponse::Ok().json(comments))
 }
 
 /// Update an existing comment.
@@ -41,43 +44,56 @@ pub async fn get_all_comments_by_post_id(path: web::Path<u32>) -> impl Responder
     request_body = CommentToUpdate,
     responses(
         (status = 200, description = "Comment updated successfully."),
+        (status = 400, description = "Path ID does not match the request body."),
+        (status = 403, description = "Only the comment author can update it."),
+        (status = 404, description = "Comment not found."),
         (status = 500, description = "Internal server error occurred.")
     )
 )]
 #[put("/comment/update/{id}")]
-pub async fn update_existing_comment(data: web::Json<CommentToUpdate>) -> impl Responder {
-    match sql_operations::update_comment(data.into_inner()).await {
-        true => HttpResponse::Ok().json("Comment updated successfully."),
-        false => {
-            error!("Failed to update comment");
-            HttpResponse::InternalServerError().finish()
-        }
+pub async fn update_existing_comment(
+    user: AuthenticatedUser,
+    path: web::Path<u32>,
+    data: web::Json<CommentToUpdate>,
+) -> Result<HttpResponse, AppError> {
+    if path.into_inner() != data.comment_id {
+        return Err(AppError::Validation(
+            "Comment ID in path does not match the request body.".to_string(),
+        ));
     }
+    let author_id = sql_operations::get_comment_author(data.comment_id).await?;
+    user.ensure_owner(author_id)?;
+
+    sql_operations::update_comment(data.into_inner()).await?;
+    Ok(HttpResponse::Ok().json("Comment updated successfully."))
 }
 
 /// Delete an existing comment by ID.
 #[utoipa::path(
     responses(
         (status = 200, description = "Comment deleted successfully."),
+        (status = 403, description = "Only the comment author can delete it."),
         (status = 404, description = "Comment not found."),
         (status = 500, description = "Internal server error occurred.")
     )
 )]
 #[delete("/comment/delete/{id}")]
-pub async fn delete_existing_comment(path: web::Path<u32>) -> impl Responder {
+pub async fn delete_existing_comment(
+    user: AuthenticatedUser,
+    path: web::Path<u32>,
+) -> Result<HttpResponse, AppError> {
     let comment_id = path.into_inner();
-    match sql_operations::delete_comment(comment_id).await {
-        true => HttpResponse::Ok().json("Comment deleted successfully."),
-        false => {
-            error!("Failed to delete comment");
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    let author_id = sql_operations::get_comment_author(comment_id).await?;
+    user.ensure_owner(author_id)?;
+
+    sql_operations::delete_comment(comment_id).await?;
+    Ok(HttpResponse::Ok().json("Comment deleted successfully."))
 }
 
 /// Retrieve the average rating for a post.
 #[get("/rating/{id}")]
-pub async fn get_avg_rating(path: web::Path<u32>) -> impl Responder {
+pub async fn get_avg_rating(path: web::Path<u32>) -> Result<HttpResponse, AppError> {
     let post_id = path.into_inner();
-    HttpResponse::Ok().json(sql_operations::get_avg_rating(post_id).await)
+    let rating = sql_operations::get_avg_rating(post_id).await?;
+    Ok(HttpResponse::Ok().json(rating))
 }
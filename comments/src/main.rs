@@ -11,6 +11,8 @@ use utoipa_swagger_ui::SwaggerUi;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    data_access::get_pool().expect("Failed to create database pool");
+
     HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
@@ -36,6 +38,8 @@ async fn main() -> std::io::Result<()> {
         let openapi = ApiDoc::openapi();
 
         App::new()
+            .app_data(data_access::error::json_config())
+            .app_data(data_access::error::path_config())
             .wrap(HttpAuthentication::bearer(validate_jwt))
             .wrap(cors)
             .service(
@@ -1,25 +1,23 @@
 use crate::comment::{Comment, CommentToInsert, CommentToUpdate};
+use data_access::{AppError, AppResult};
 use mysql::{params, prelude::Queryable, Row, Value};
 
-pub async fn get_avg_rating(post_id: u32) -> f32 {
-    let mut conn = data_access::get_connection();
-
+pub async fn get_avg_rating(post_id: u32) -> AppResult<f32> {
     let query = "SELECT AVG(rating) FROM comments WHERE post_id = :post_id";
 
-    let result = conn
-        .exec(query, params! { "post_id" => post_id })
-        .expect("wrong");
+    let result: Option<Option<f32>> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "post_id" => post_id })
+    })
+    .await?;
 
-    result.into_iter().next().unwrap()
+    Ok(result.flatten().unwrap_or_default())
 }
 
-pub async fn comment(request: CommentToInsert) -> bool {
-    let mut conn = data_access::get_connection();
-
+pub async fn comment(request: CommentToInsert) -> AppResult<()> {
     let query = "INSERT INTO comments (post_id, user_id, comment, publish_date, rating) VALUES (:post_id, :user_id, :comment, CURDATE(), :rating)";
 
-    let result = conn
-        .exec_iter(
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(
             query,
             params! {
             "post_id" => request.post_id,
@@ -28,19 +26,17 @@ pub async fn comment(request: CommentToInsert) -> bool {
             "rating" => request.rating
             },
         )
-        .expect("Failed to execute register query")
-        .affected_rows();
+    })
+    .await?;
 
-    result == 1
+    Ok(())
 }
 
-pub async fn get_all_comments(post_id: u32) -> Vec<Comment> {
-    let mut conn = data_access::get_connection();
-
+pub async fn get_all_comments(post_id: u32) -> AppResult<Vec<Comment>> {
     let query = "SELECT comment_id, post_id, user_id, comment, publish_date, rating FROM comments WHERE post_id = :post_id";
 
-    let comments: Vec<Comment> = conn
-        .exec_map(query, params! { "post_id" => post_id }, |row: Row| {
+    let comments = data_access::with_connection(move |conn| {
+        conn.exec_map(query, params! { "post_id" => post_id }, |row: Row| {
             let publish_date_value: Value =
                 row.get("publish_date").expect("Failed to get publish_date");
             let publish_date_str = match publish_date_value {
@@ -59,18 +55,17 @@ pub async fn get_all_comments(post_id: u32) -> Vec<Comment> {
                 rating: row.get("rating").unwrap_or_default(),
             }
         })
-        .expect("Failed to execute select query and map results");
+    })
+    .await?;
 
-    comments
+    Ok(comments)
 }
 
-pub async fn update_comment(request: CommentToUpdate) -> bool {
-    let mut conn = data_access::get_connection();
-
+pub async fn update_comment(request: CommentToUpdate) -> AppResult<()> {
     let query = "UPDATE comments SET comment = :comment, publish_date = CURDATE(), rating = :rating WHERE comment_id = :comment_id";
 
-    let result = conn
-        .exec_iter(
+    let result = data_access::with_connection(move |conn| {
+        conn.exec_iter(
             query,
             params! {
             "comment_id" => request.comment_id,
@@ -78,37 +73,56 @@ pub async fn update_comment(request: CommentToUpdate) -> bool {
             "rating" => request.rating,
             },
         )
-        .expect("Failed to execute register query")
-        .affected_rows();
+        .map(|result| result.affected_rows())
+    })
+    .await?;
 
-    result == 1
-}
+    if result == 0 {
+        return Err(AppError::NotFound("Comment not found.".to_string()));
+    }
 
-pub async fn delete_comment(id: u32) -> bool {
-    let mut conn = data_access::get_connection();
+    Ok(())
+}
 
+pub async fn delete_comment(id: u32) -> AppResult<()> {
     let query = "DELETE FROM comments WHERE comment_id = :comment_id";
 
-    let result = conn
-        .exec_iter(
+    let result = data_access::with_connection(move |conn| {
+        conn.exec_iter(
             query,
             params! {
             "comment_id" => id
             },
         )
-        .expect("Failed to execute register query")
-        .affected_rows();
+        .map(|result| result.affected_rows())
+    })
+    .await?;
+
+    if result == 0 {
+        return Err(AppError::NotFound("Comment not found.".to_string()));
+    }
+
+    Ok(())
+}
+
+pub async fn get_comment_author(comment_id: u32) -> AppResult<u32> {
+    let query = "SELECT user_id FROM comments WHERE comment_id = :comment_id";
+
+    let result: Option<u32> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "comment_id" => comment_id })
+    })
+    .await?;
 
-    result == 1
+    result.ok_or_else(|| AppError::NotFound("Comment not found.".to_string()))
 }
 
 //only for tests
 pub async fn _get_last_comment_id() -> u32 {
-    let mut conn = data_access::get_connection();
-
     let query = "SELECT MAX(comment_id) FROM comments";
 
-    let result: Option<u32> = conn.query_first(query).expect("Failed to execute query");
+    let result: Option<u32> = data_access::with_connection(move |conn| conn.query_first(query))
+        .await
+        .expect("Failed to execute query");
 
     result.unwrap()
 }
@@ -119,7 +133,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_avg_rating() {
-        let result = get_avg_rating(1).await;
+        let result = get_avg_rating(1).await.unwrap();
         println!("Result: {}", result);
         assert!(result > 0.0);
     }
@@ -133,12 +147,12 @@ mod tests {
             rating: 5,
         };
         let result = comment(comment_to_insert).await;
-        assert!(result);
+        assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn test_get_all_comments() {
-        let result = get_all_comments(1).await;
+        let result = get_all_comments(1).await.unwrap();
         assert!(!result.is_empty());
     }
 
@@ -151,13 +165,25 @@ mod tests {
         };
 
         let result = update_comment(comment_to_update).await;
-        assert!(result);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_get_comment_author() {
+        let result = get_comment_author(_get_last_comment_id().await).await;
+        assert!(result.unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_comment_author_invalid() {
+        let result = get_comment_author(0).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
     }
 
     #[tokio::test]
     async fn test_delete_comment() {
         let result = delete_comment(_get_last_comment_id().await).await;
-        assert!(result);
+        assert!(result.is_ok());
     }
 
     #[tokio::test]
@@ -169,12 +195,12 @@ mod tests {
             rating: 5,
         };
         let result = comment(comment_to_insert).await;
-        assert!(result);
+        assert!(matches!(result, Err(AppError::Validation(_))));
     }
 
     #[tokio::test]
     async fn test_get_all_comments_invalid() {
-        let result = get_all_comments(0).await;
+        let result = get_all_comments(0).await.unwrap();
         assert!(!result.is_empty());
     }
 
@@ -187,12 +213,12 @@ mod tests {
         };
 
         let result = update_comment(comment_to_update).await;
-        assert!(result);
+        assert!(matches!(result, Err(AppError::NotFound(_))));
     }
 
     #[tokio::test]
     async fn test_delete_comment_invalid() {
         let result = delete_comment(0).await;
-        assert!(result);
+        assert!(matches!(result, Err(AppError::NotFound(_))));
     }
 }
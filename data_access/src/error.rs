@@ -0,0 +1,222 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use log::error;
+use serde::Serialize;
+use std::fmt;
+
+/// MySQL server error codes that map to client errors rather than internal ones.
+const ER_DUP_ENTRY: u16 = 1062;
+const ER_ROW_IS_REFERENCED: u16 = 1451;
+const ER_NO_REFERENCED_ROW: u16 = 1452;
+const ER_DATA_TOO_LONG: u16 = 1406;
+const ER_CHECK_CONSTRAINT_VIOLATED: u16 = 3819;
+
+/// Error shared by every service. Converts into an actix response or a tonic status,
+/// both carrying the same `code` so clients can branch on it.
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    Conflict(String),
+    Validation(String),
+    Unauthorized(String),
+    Forbidden(String),
+    /// Rate limits and lockouts.
+    TooManyRequests(String),
+    /// Size limits and storage quotas.
+    QuotaExceeded(String),
+    /// The message is logged but never sent to the client.
+    Internal(String),
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+/// JSON body returned for every error response:
+/// `{"error": {"code": "not_found", "message": "User not found."}}`
+#[derive(Serialize)]
+pub struct ErrorBody {
+    pub error: ErrorDetail,
+}
+
+#[derive(Serialize)]
+pub struct ErrorDetail {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::Validation(_) => "validation",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::TooManyRequests(_) => "too_many_requests",
+            AppError::QuotaExceeded(_) => "quota_exceeded",
+            AppError::Internal(_) => "internal",
+        }
+    }
+
+    /// Message safe to return to clients.
+    pub fn public_message(&self) -> String {
+        match self {
+            AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::Validation(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::TooManyRequests(message)
+            | AppError::QuotaExceeded(message) => message.clone(),
+            AppError::Internal(_) => "Internal server error occurred.".to_string(),
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            error: ErrorDetail {
+                code: self.code(),
+                message: self.public_message(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(message) => write!(f, "not found: {}", message),
+            AppError::Conflict(message) => write!(f, "conflict: {}", message),
+            AppError::Validation(message) => write!(f, "validation failed: {}", message),
+            AppError::Unauthorized(message) => write!(f, "unauthorized: {}", message),
+            AppError::Forbidden(message) => write!(f, "forbidden: {}", message),
+            AppError::TooManyRequests(message) => write!(f, "too many requests: {}", message),
+            AppError::QuotaExceeded(message) => write!(f, "quota exceeded: {}", message),
+            AppError::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<mysql::Error> for AppError {
+    fn from(e: mysql::Error) -> Self {
+        if let mysql::Error::MySqlError(ref server_error) = e {
+            match server_error.code {
+                ER_DUP_ENTRY => return AppError::Conflict("Resource already exists.".to_string()),
+                ER_ROW_IS_REFERENCED => {
+                    return AppError::Conflict("Resource is still referenced.".to_string())
+                }
+                ER_NO_REFERENCED_ROW => {
+                    return AppError::Validation("Referenced resource does not exist.".to_string())
+                }
+                ER_DATA_TOO_LONG | ER_CHECK_CONSTRAINT_VIOLATED => {
+                    return AppError::Validation(server_error.message.clone())
+                }
+                _ => {}
+            }
+        }
+
+        AppError::Internal(e.to_string())
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::QuotaExceeded(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let AppError::Internal(message) = self {
+            error!("Internal error: {}", message);
+        }
+        HttpResponse::build(self.status_code()).json(self.body())
+    }
+}
+
+/// JSON extractor config that reports malformed request bodies with the same error envelope.
+pub fn json_config() -> actix_web::web::JsonConfig {
+    actix_web::web::JsonConfig::default()
+        .error_handler(|e, _| AppError::Validation(e.to_string()).into())
+}
+
+/// Path extractor config that reports malformed path parameters with the same error envelope.
+pub fn path_config() -> actix_web::web::PathConfig {
+    actix_web::web::PathConfig::default()
+        .error_handler(|e, _| AppError::Validation(e.to_string()).into())
+}
+
+/// Query string extractor config that reports malformed parameters with the same error envelope.
+pub fn query_config() -> actix_web::web::QueryConfig {
+    actix_web::web::QueryConfig::default()
+        .error_handler(|e, _| AppError::Validation(e.to_string()).into())
+}
+
+impl From<AppError> for tonic::Status {
+    fn from(e: AppError) -> Self {
+        let message = e.public_message();
+        match e {
+            AppError::NotFound(_) => tonic::Status::not_found(message),
+            AppError::Conflict(_) => tonic::Status::already_exists(message),
+            AppError::Validation(_) => tonic::Status::invalid_argument(message),
+            AppError::Unauthorized(_) => tonic::Status::unauthenticated(message),
+            AppError::Forbidden(_) => tonic::Status::permission_denied(message),
+            AppError::TooManyRequests(_) | AppError::QuotaExceeded(_) => {
+                tonic::Status::resource_exhausted(message)
+            }
+            AppError::Internal(internal) => {
+                error!("Internal error: {}", internal);
+                tonic::Status::internal(message)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mysql::MySqlError;
+
+    fn server_error(code: u16) -> mysql::Error {
+        mysql::Error::MySqlError(MySqlError {
+            state: "23000".to_string(),
+            message: "server message".to_string(),
+            code,
+        })
+    }
+
+    #[test]
+    fn test_duplicate_entry_is_conflict() {
+        let e = AppError::from(server_error(ER_DUP_ENTRY));
+        assert_eq!(e.status_code(), StatusCode::CONFLICT);
+        assert_eq!(e.code(), "conflict");
+    }
+
+    #[test]
+    fn test_missing_reference_is_validation() {
+        let e = AppError::from(server_error(ER_NO_REFERENCED_ROW));
+        assert_eq!(e.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_internal_message_is_hidden() {
+        let e = AppError::from(server_error(2013));
+        assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!e.public_message().contains("server message"));
+    }
+
+    #[test]
+    fn test_tonic_status_code() {
+        let status = tonic::Status::from(AppError::NotFound("Post not found.".to_string()));
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(status.message(), "Post not found.");
+    }
+}
@@ -1,17 +1,163 @@
-use mysql::{Opts, Pool, PooledConn};
+pub mod error;
 
-pub fn get_connection() -> PooledConn {
-    let url =
-        std::env::var("DATABASE_URL").expect("Couldn't get secret key from cargo environment");
-    let pool = Pool::new(Opts::from_url(&url).unwrap()).expect("Failed to create pool");
+pub use error::{AppError, AppResult};
 
-    pool.get_conn().expect("Failed to get connection")
+use mysql::consts::CapabilityFlags;
+use mysql::{Opts, OptsBuilder, Pool, PoolConstraints, PoolOpts, PooledConn, UrlError};
+use std::io::ErrorKind;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static POOL: OnceLock<DbPool> = OnceLock::new();
+
+/// Settings used to build the process-wide connection pool.
+///
+/// Every value is read from the environment, falling back to the defaults below:
+/// `DB_POOL_MIN_SIZE` (1), `DB_POOL_MAX_SIZE` (10), `DB_POOL_ACQUIRE_TIMEOUT_SECS` (5)
+/// and `DB_POOL_CHECK_HEALTH` (true).
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub url: String,
+    pub min_size: usize,
+    pub max_size: usize,
+    pub acquire_timeout: Duration,
+    pub check_health: bool,
+}
+
+impl PoolConfig {
+    /// Fails if `DATABASE_URL` is not set.
+    pub fn from_env() -> Result<Self, mysql::Error> {
+        let url = std::env::var("DATABASE_URL").map_err(|_| {
+            mysql::Error::IoError(std::io::Error::new(
+                ErrorKind::NotFound,
+                "DATABASE_URL is not set",
+            ))
+        })?;
+
+        Ok(PoolConfig {
+            url,
+            min_size: env_or("DB_POOL_MIN_SIZE", 1),
+            max_size: env_or("DB_POOL_MAX_SIZE", 10),
+            acquire_timeout: Duration::from_secs(env_or("DB_POOL_ACQUIRE_TIMEOUT_SECS", 5)),
+            check_health: env_or("DB_POOL_CHECK_HEALTH", true),
+        })
+    }
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// A cloneable handle to the shared MySQL pool.
+///
+/// Queries go through [`with_connection`], which uses the process-wide pool; services that
+/// hold their own handle, like the subscriptions repository, use [`DbPool::run`].
+#[derive(Clone)]
+pub struct DbPool {
+    pool: Pool,
+    acquire_timeout: Duration,
+}
+
+impl DbPool {
+    pub fn new(config: &PoolConfig) -> Result<Self, mysql::Error> {
+        let constraints =
+            PoolConstraints::new(config.min_size, config.max_size).ok_or_else(|| {
+                UrlError::InvalidValue(
+                    "DB_POOL_MIN_SIZE/DB_POOL_MAX_SIZE".to_string(),
+                    format!("{}/{}", config.min_size, config.max_size),
+                )
+            })?;
+        let pool_opts = PoolOpts::default()
+            .with_constraints(constraints)
+            .with_check_health(config.check_health);
+        // Report matched rather than changed rows, so an UPDATE that leaves a row as it was
+        // is not mistaken for a missing row.
+        let opts = OptsBuilder::from_opts(Opts::from_url(&config.url)?)
+            .pool_opts(pool_opts)
+            .additional_capabilities(CapabilityFlags::CLIENT_FOUND_ROWS);
+
+        Ok(DbPool {
+            pool: Pool::new(opts)?,
+            acquire_timeout: config.acquire_timeout,
+        })
+    }
+
+    pub fn get_conn(&self) -> Result<PooledConn, mysql::Error> {
+        self.pool.try_get_conn(self.acquire_timeout)
+    }
+
+    /// Runs `f` with a pooled connection on tokio's blocking thread pool, so the
+    /// synchronous `mysql` calls never stall the async worker that awaits them.
+    pub async fn run<F, T, E>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut PooledConn) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<mysql::Error> + Send + 'static,
+    {
+        let pool = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get_conn()?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| E::from(mysql::Error::IoError(std::io::Error::other(e))))?
+    }
+}
+
+/// Returns the process-wide pool, creating it from the environment on first use.
+pub fn get_pool() -> Result<&'static DbPool, mysql::Error> {
+    if let Some(pool) = POOL.get() {
+        return Ok(pool);
+    }
+
+    let pool = DbPool::new(&PoolConfig::from_env()?)?;
+    Ok(POOL.get_or_init(|| pool))
+}
+
+/// Runs `f` against the process-wide pool. See [`DbPool::run`].
+pub async fn with_connection<F, T, E>(f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut PooledConn) -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: From<mysql::Error> + Send + 'static,
+{
+    let pool = tokio::task::spawn_blocking(get_pool)
+        .await
+        .map_err(|e| E::from(mysql::Error::IoError(std::io::Error::other(e))))?
+        .map_err(E::from)?;
+
+    pool.run(f).await
 }
 
-pub fn get_connection_safe() -> Result<PooledConn, mysql::Error> {
-    let url =
-        std::env::var("DATABASE_URL").expect("Couldn't get secret key from cargo environment");
-    let pool = Pool::new(Opts::from_url(&url).unwrap())?;
-    let conn = pool.get_conn()?;
-    Ok(conn)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_pool_size() {
+        let config = PoolConfig {
+            url: "mysql://root@localhost:3306/studyvault".to_string(),
+            min_size: 10,
+            max_size: 2,
+            acquire_timeout: Duration::from_secs(5),
+            check_health: true,
+        };
+
+        let result = DbPool::new(&config);
+        assert!(matches!(result, Err(mysql::Error::UrlError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_missing_database_url() {
+        std::env::remove_var("DATABASE_URL");
+
+        let result: Result<(), AppError> = with_connection(|_| Ok(())).await;
+        match result {
+            Err(AppError::Internal(message)) => assert!(message.contains("DATABASE_URL")),
+            _ => panic!("expected a configuration error"),
+        }
+    }
 }
@@ -8,7 +8,9 @@ create table users(
     name varchar(32) not null,
     last_name varchar(64) not null,
     email varchar(64) not null,
-    password varchar(64) not null,
+    password varchar(255) not null,
+    email_verified boolean not null default false,
+    storage_quota_bytes bigint unsigned,
     primary key(user_id),
     unique(user_id),
     unique(email)
@@ -40,8 +42,11 @@ create table channels(
     name varchar(32) not null,
     description varchar(256),
     category_id int not null,
+    visibility enum('public', 'members') not null default 'public',
+    storage_quota_bytes bigint unsigned,
     primary key(channel_id),
-    unique(channel_id)
+    unique(channel_id),
+    fulltext(name, description)
 );
 
 create table posts(
@@ -53,7 +58,8 @@ create table posts(
     publish_date date not null,
     primary key(post_id),
     unique(post_id),
-    unique(file_id)
+    unique(file_id),
+    fulltext(title, description)
 );
 
 create table comments(
@@ -64,16 +70,97 @@ create table comments(
     publish_date date not null,
     rating int not null check (rating between 0 and 5),
     primary key(comment_id),
-    unique(comment_id)
+    unique(comment_id),
+    fulltext(comment)
 );
 
 create table files(
     file_id varchar(36) not null,
     name varchar(256) not null,
+    size bigint unsigned not null default 0,
+    sha256 char(64),
+    mime_type varchar(127),
+    uploader_id int,
     primary key(file_id),
     unique(file_id)
 );
 
+create table blobs(
+    sha256 char(64) not null,
+    size bigint unsigned not null,
+    ref_count int unsigned not null default 0,
+    stored boolean not null default false,
+    primary key(sha256)
+);
+
+create table blob_texts(
+    sha256 char(64) not null,
+    status enum('extracted', 'unsupported', 'too_large', 'failed') not null,
+    content mediumtext,
+    extracted_at datetime not null,
+    primary key(sha256),
+    fulltext(content)
+);
+
+create table blob_previews(
+    sha256 char(64) not null,
+    status enum('generated', 'unsupported', 'too_large', 'failed') not null,
+    width int unsigned not null default 0,
+    height int unsigned not null default 0,
+    generated_at datetime not null,
+    primary key(sha256)
+);
+
+create table upload_sessions(
+    session_id varchar(36) not null,
+    user_id int not null,
+    channel_id int not null,
+    file_name varchar(256) not null,
+    title varchar(32) not null,
+    description varchar(256),
+    total_size bigint unsigned not null,
+    created_at datetime not null,
+    expires_at datetime not null,
+    file_id varchar(36),
+    primary key(session_id)
+);
+
+create table sessions(
+    session_id varchar(32) not null,
+    user_id int not null,
+    refresh_token_hash char(64) not null,
+    created_at datetime not null,
+    expires_at datetime not null,
+    revoked_at datetime,
+    primary key(session_id),
+    index(user_id)
+);
+
+create table rotated_refresh_tokens(
+    refresh_token_hash char(64) not null,
+    session_id varchar(32) not null,
+    rotated_at datetime not null,
+    primary key(refresh_token_hash)
+);
+
+create table verification_codes(
+    email varchar(64) not null,
+    purpose enum('verify_email', 'reset_password') not null default 'verify_email',
+    code_hash char(64) not null,
+    attempts int not null default 0,
+    expires_at datetime not null,
+    primary key(email, purpose)
+);
+
+create table verification_requests(
+    request_id int not null auto_increment,
+    email varchar(64) not null,
+    ip_address varchar(45) not null,
+    requested_at datetime not null,
+    primary key(request_id),
+    index(requested_at)
+);
+
 -- foreign keys
 
 alter table users
@@ -91,21 +178,43 @@ alter table posts
 add constraint fk_posts_channels foreign key(channel_id) references channels(channel_id) on delete cascade on update cascade,
 add constraint fk_posts_files foreign key(file_id) references files(file_id) on delete cascade on update cascade;
 
+alter table files
+add constraint fk_files_users foreign key(uploader_id) references users(user_id) on delete set null on update cascade,
+add constraint fk_files_blobs foreign key(sha256) references blobs(sha256) on update cascade;
+
+alter table blob_texts
+add constraint fk_blob_texts_blobs foreign key(sha256) references blobs(sha256) on delete cascade on update cascade;
+
+alter table blob_previews
+add constraint fk_blob_previews_blobs foreign key(sha256) references blobs(sha256) on delete cascade on update cascade;
+
 alter table comments
 add constraint fk_comments_post foreign key(post_id) references posts(post_id) on delete cascade on update cascade,
 add constraint fk_comments_users foreign key(user_id) references users(user_id) on delete cascade on update cascade;
 
+alter table upload_sessions
+add constraint fk_upload_sessions_users foreign key(user_id) references users(user_id) on delete cascade on update cascade,
+add constraint fk_upload_sessions_channels foreign key(channel_id) references channels(channel_id) on delete cascade on update cascade;
+
+alter table sessions
+add constraint fk_sessions_users foreign key(user_id) references users(user_id) on delete cascade on update cascade;
+
+alter table rotated_refresh_tokens
+add constraint fk_rotated_refresh_tokens_sessions foreign key(session_id) references sessions(session_id) on delete cascade on update cascade;
+
 -- startup data
 
 insert into user_types(user_type) values('Professor');
 insert into user_types(user_type) values('Student');
+insert into user_types(user_type) values('Admin');
 
-insert into users(user_type_id, name, last_name, email, password) values(2, 'Camilo', 'Espejo Sánchez', 'zs21013861@estudiantes.uv.mx', '123456');
-insert into users(user_type_id, name, last_name, email, password) values(1, 'Lizbeth', 'Rodríguez Mesa', 'lizrm@uv.mx', '123456');
+-- users with plaintext passwords, upgraded to Argon2id on first login
+insert into users(user_type_id, name, last_name, email, password, email_verified) values(2, 'Camilo', 'Espejo Sánchez', 'zs21013861@estudiantes.uv.mx', '123456', true);
+insert into users(user_type_id, name, last_name, email, password, email_verified) values(1, 'Lizbeth', 'Rodríguez Mesa', 'lizrm@uv.mx', '123456', true);
 
--- users with hashed password
-insert into users(user_type_id, name, last_name, email, password) values(1, 'Juan', 'Sánchez Meza', 'juan@uv.mx', 'ed08c290d7e22f7bb324b15cbadce35b0b348564fd2d5f95752388d86d71bcca'); -- password is juan
-insert into users(user_type_id, name, last_name, email, password) values(2, 'Alejandra', 'Carabantes Martínez', 'zs21013862@estudiantes.uv.mx', '069fca009882e13e01c6b0559c9b14a4337c4495f83fd720965ec80f0770a699'); -- password is alejandra
+-- users with client-side SHA-256 passwords, upgraded to Argon2id on first login
+insert into users(user_type_id, name, last_name, email, password, email_verified) values(1, 'Juan', 'Sánchez Meza', 'juan@uv.mx', 'ed08c290d7e22f7bb324b15cbadce35b0b348564fd2d5f95752388d86d71bcca', true); -- password is juan
+insert into users(user_type_id, name, last_name, email, password, email_verified) values(2, 'Alejandra', 'Carabantes Martínez', 'zs21013862@estudiantes.uv.mx', '069fca009882e13e01c6b0559c9b14a4337c4495f83fd720965ec80f0770a699', true); -- password is alejandra
 
 insert into categories(name) values('Ingeniería de Requisitos');
 insert into categories(name) values('Arquitectura de Software');
@@ -0,0 +1,74 @@
+use crate::sql_operations;
+use auth::AuthenticatedUser;
+use data_access::{AppError, AppResult};
+
+/// What decides whether a caller may list and download a channel's posts.
+pub struct ChannelAccess {
+    pub creator_id: u32,
+    pub members_only: bool,
+    pub subscribed: bool,
+}
+
+impl ChannelAccess {
+    /// Public channels are open to every authenticated user. Members-only channels are
+    /// limited to the creator, subscribers and administrators.
+    pub fn allows(&self, user: &AuthenticatedUser) -> bool {
+        !self.members_only || self.subscribed || user.ensure_owner(self.creator_id).is_ok()
+    }
+}
+
+pub async fn ensure_can_view(user: &AuthenticatedUser, channel_id: u32) -> AppResult<()> {
+    let access = sql_operations::get_channel_access(channel_id, user.user_id).await?;
+
+    if access.allows(user) {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden(
+            "Only members can access this channel.".to_string(),
+        ))
+    }
+}
+
+/// Posts are managed by the channel's creator, like uploads.
+pub async fn ensure_can_manage(user: &AuthenticatedUser, channel_id: u32) -> AppResult<()> {
+    let creator_id = sql_operations::get_channel_creator(channel_id).await?;
+    user.ensure_owner(creator_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use auth::Role;
+
+    fn user(user_id: u32, role: Role) -> AuthenticatedUser {
+        AuthenticatedUser { user_id, role }
+    }
+
+    #[test]
+    fn test_public_channel_allows_everyone() {
+        let access = ChannelAccess {
+            creator_id: 2,
+            members_only: false,
+            subscribed: false,
+        };
+        assert!(access.allows(&user(1, Role::Student)));
+    }
+
+    #[test]
+    fn test_members_only_channel() {
+        let access = ChannelAccess {
+            creator_id: 2,
+            members_only: true,
+            subscribed: false,
+        };
+        assert!(!access.allows(&user(1, Role::Student)));
+        assert!(access.allows(&user(2, Role::Professor)));
+        assert!(access.allows(&user(5, Role::Administrator)));
+
+        let subscribed = ChannelAccess {
+            subscribed: true,
+            ..access
+        };
+        assert!(subscribed.allows(&user(1, Role::Student)));
+    }
+}
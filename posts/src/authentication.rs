@@ -0,0 +1,52 @@
+use auth::{AuthenticatedUser, Claims};
+use tonic::{Extensions, Request, Status};
+
+/// Tonic interceptor that validates the `authorization: Bearer <jwt>` metadata and stores
+/// the decoded [`Claims`] in the request extensions.
+#[allow(clippy::result_large_err)] // signature required by tonic's `Interceptor`
+pub fn intercept(mut request: Request<()>) -> Result<Request<()>, Status> {
+    let token = request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| Status::unauthenticated("Missing bearer token."))?;
+
+    let claims = auth::decode_jwt(token).map_err(|_| Status::unauthenticated("Invalid token."))?;
+    request.extensions_mut().insert(claims);
+
+    Ok(request)
+}
+
+/// The caller of a request that went through [`intercept`]. The session lookup happens
+/// here because interceptors can't run async code.
+pub async fn caller(extensions: &Extensions) -> Result<AuthenticatedUser, Status> {
+    let claims = extensions
+        .get::<Claims>()
+        .ok_or_else(|| Status::unauthenticated("Missing bearer token."))?;
+
+    auth::check_session(claims).await?;
+    Ok(AuthenticatedUser::from_claims(claims))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intercept_missing_token() {
+        let result = intercept(Request::new(()));
+        assert_eq!(result.unwrap_err().code(), tonic::Code::Unauthenticated);
+    }
+
+    #[test]
+    fn test_intercept_wrong_scheme() {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", "Basic dXNlcjpwYXNz".parse().unwrap());
+
+        let result = intercept(request);
+        assert_eq!(result.unwrap_err().code(), tonic::Code::Unauthenticated);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::local_store::LocalFileStore;
+use crate::s3_store::{S3Config, S3FileStore};
+use async_trait::async_trait;
+use data_access::{AppError, AppResult};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub type FileReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// An object as reported by [`FileStore::list`].
+pub struct StoredObject {
+    pub key: String,
+    pub modified: SystemTime,
+}
+
+/// Part of a stored object; `length: None` reads up to the end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteRange {
+    pub offset: u64,
+    pub length: Option<u64>,
+}
+
+impl ByteRange {
+    /// The range a client asked for of an object of `total` bytes, where a `length` of 0
+    /// means up to the end. Lengths past the end are cut short.
+    pub fn resolve(offset: u64, length: u64, total: u64) -> AppResult<Self> {
+        if offset > total {
+            return Err(AppError::Validation(format!(
+                "Offset {} is past the end of the file ({} bytes).",
+                offset, total
+            )));
+        }
+
+        let available = total - offset;
+        let length = if length == 0 {
+            available
+        } else {
+            length.min(available)
+        };
+
+        Ok(ByteRange {
+            offset,
+            length: Some(length),
+        })
+    }
+
+    /// The value of an HTTP `Range` header for this range, if it isn't the whole object.
+    pub fn http_header(&self) -> Option<String> {
+        match self.length {
+            Some(length) => Some(format!(
+                "bytes={}-{}",
+                self.offset,
+                self.offset + length.saturating_sub(1)
+            )),
+            None if self.offset > 0 => Some(format!("bytes={}-", self.offset)),
+            None => None,
+        }
+    }
+}
+
+/// Where post files are kept. Content is stored once under [`blob_key`] and its preview
+/// under [`preview_key`]; files uploaded before hashing was introduced live under
+/// [`file_key`].
+#[async_trait]
+pub trait FileStore: Send + Sync {
+    /// Stores the finished upload at `source` under `key`. `source` may be moved or left
+    /// behind; the caller removes it either way.
+    async fn put(&self, key: &str, source: &Path) -> AppResult<()>;
+    /// Opens `range` of the object for reading, failing with `NotFound` if it doesn't exist.
+    /// Ranges must not be empty.
+    async fn get(&self, key: &str, range: ByteRange) -> AppResult<FileReader>;
+    async fn delete(&self, key: &str) -> AppResult<()>;
+    async fn list(&self) -> AppResult<Vec<StoredObject>>;
+}
+
+pub fn file_key(channel_id: u32, file_id: &str, extension: &str) -> String {
+    format!("{}/{}.{}", channel_id, file_id, extension)
+}
+
+/// Key of content with the given hex encoded SHA-256, shared by every file with that content.
+pub fn blob_key(sha256: &str) -> String {
+    format!("blobs/{}", sha256)
+}
+
+/// The SHA-256 of the content stored under `key`, if it is shared content.
+pub fn blob_sha256(key: &str) -> Option<&str> {
+    key.strip_prefix("blobs/")
+}
+
+/// Key of the PNG preview of the content with the given hex encoded SHA-256.
+pub fn preview_key(sha256: &str) -> String {
+    format!("previews/{}.png", sha256)
+}
+
+/// Reads a whole object into memory.
+pub async fn read_all(store: &dyn FileStore, key: &str) -> AppResult<Vec<u8>> {
+    let mut reader = store.get(key, ByteRange::default()).await?;
+    let mut content = Vec::new();
+    reader
+        .read_to_end(&mut content)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to read {}: {}", key, e)))?;
+    Ok(content)
+}
+
+/// Builds the store selected by `FILE_STORE`: `local` (default, under `FILE_DIR`) or `s3`.
+pub fn from_env() -> AppResult<Arc<dyn FileStore>> {
+    let store: Arc<dyn FileStore> = match std::env::var("FILE_STORE")
+        .unwrap_or_else(|_| "local".to_string())
+        .as_str()
+    {
+        "local" => Arc::new(LocalFileStore::new(
+            std::env::var("FILE_DIR")
+                .map_err(|_| AppError::Internal("FILE_DIR is not set".to_string()))?,
+        )),
+        "s3" => Arc::new(S3FileStore::new(S3Config::from_env()?)),
+        other => {
+            return Err(AppError::Internal(format!(
+                "Unknown FILE_STORE \"{}\"",
+                other
+            )))
+        }
+    };
+
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_key() {
+        let key = file_key(3, "0b6c7f1e-5b1a-4c55-9a39-5d1f0b7c9e21", "pdf");
+        assert_eq!(key, "3/0b6c7f1e-5b1a-4c55-9a39-5d1f0b7c9e21.pdf");
+    }
+
+    #[test]
+    fn test_resolve_range() {
+        let range = ByteRange::resolve(0, 0, 100).unwrap();
+        assert_eq!(range.length, Some(100));
+        assert_eq!(range.http_header().unwrap(), "bytes=0-99");
+
+        let range = ByteRange::resolve(90, 64, 100).unwrap();
+        assert_eq!(range.length, Some(10));
+        assert_eq!(range.http_header().unwrap(), "bytes=90-99");
+
+        assert_eq!(ByteRange::resolve(100, 0, 100).unwrap().length, Some(0));
+        assert!(matches!(
+            ByteRange::resolve(101, 0, 100),
+            Err(AppError::Validation(_))
+        ));
+
+        assert_eq!(ByteRange::default().http_header(), None);
+    }
+
+    #[test]
+    fn test_blob_key() {
+        let sha256 = "ed7002b439e9ac845f22357d822bac1444730fbdb6016d3ec9432297b9ec9f73";
+        assert_eq!(blob_key(sha256), format!("blobs/{}", sha256));
+        assert_eq!(blob_sha256(&blob_key(sha256)), Some(sha256));
+        assert_eq!(blob_sha256("1/file.pdf"), None);
+        assert_eq!(preview_key(sha256), format!("previews/{}.png", sha256));
+    }
+}
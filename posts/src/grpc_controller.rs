@@ -1,22 +1,267 @@
+use crate::access;
+use crate::authentication;
+use crate::file_store::{self, ByteRange, FileStore};
+use crate::listing::{self, PageCursor, PostOrder, PostQuery};
+use crate::post::{FeedPost, NewFile, NewPost, Post, UploadSession};
+use crate::posts::feed_response::FeedPost as FeedPostInfo;
+use crate::posts::file_chunk::Data;
 use crate::posts::posts_response::PostInfo;
 use crate::posts::posts_service_server::PostsService;
+use crate::posts::replace_file_chunk::Data as ReplaceData;
+use crate::posts::upload_part_chunk::Data as PartData;
+use crate::posts::PostOrder as ProtoPostOrder;
+use crate::posts::PreviewStatus as ProtoPreviewStatus;
 use crate::posts::{
-    ChannelRequest, FileChunk, FileData, FileDownloadRequest, FileId, FileName, PostsResponse,
+    ChannelRequest, CreateUploadSessionRequest, DeletePostResponse, DownloadInfo, FeedRequest,
+    FeedResponse, FileChunk, FileData, FileDownloadRequest, FileId, FileName, PostRequest,
+    PostsResponse, PreviewResponse, ReplaceFileChunk, StorageUsageRequest, StorageUsageResponse,
+    UpdatePostRequest, UploadMetadata, UploadPartChunk, UploadSessionRequest, UploadSessionStatus,
     UploadStatusResponse,
 };
+use crate::previews::{self, PreviewGenerator, PreviewStatus};
+use crate::quota::QuotaLimits;
 use crate::sql_operations;
+use crate::storage::{self, PendingFile};
+use crate::text_extraction::TextExtractor;
+use crate::upload;
+use crate::upload_session::{self, ActiveSessions};
 use async_stream::try_stream;
+use auth::Role;
+use data_access::{AppError, AppResult};
 use futures_util::{Stream, StreamExt};
 use log::{error, info};
 use std::pin::Pin;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
-use tokio::io::AsyncWriteExt;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tonic::{Request, Response, Status};
 use uuid::Uuid;
 
-#[derive(Default)]
-pub struct PostsServicesStruct;
+/// Size of the content messages sent by `DownloadFile`.
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct PostsServicesStruct {
+    store: Arc<dyn FileStore>,
+    sessions: ActiveSessions,
+    extractor: TextExtractor,
+    previews: PreviewGenerator,
+}
+
+impl PostsServicesStruct {
+    pub fn new(
+        store: Arc<dyn FileStore>,
+        extractor: TextExtractor,
+        previews: PreviewGenerator,
+    ) -> Self {
+        PostsServicesStruct {
+            store,
+            sessions: ActiveSessions::default(),
+            extractor,
+            previews,
+        }
+    }
+
+    /// Creates the post for a fully received upload and stores its content. The upload
+    /// session the content was received in, if any, is committed along with creating the
+    /// post. Returns the content's SHA-256.
+    async fn store_upload(
+        &self,
+        uploader_id: u32,
+        metadata: UploadMetadata,
+        extension: &str,
+        file: PendingFile,
+        session_id: Option<String>,
+    ) -> Result<String, Status> {
+        let new_file = new_file(uploader_id, metadata.filename, extension, &file);
+        let (file_id, sha256) = (new_file.file_id.clone(), new_file.sha256.clone());
+        let post = NewPost {
+            channel_id: metadata.channel_id,
+            title: metadata.title,
+            description: metadata.description,
+            file: new_file,
+        };
+        let limits = QuotaLimits::from_env();
+        let created = match session_id {
+            Some(session_id) => {
+                sql_operations::commit_upload_session(session_id, post, limits).await
+            }
+            None => sql_operations::create_post(post, limits)
+                .await
+                .map(|new_content| (file_id, new_content)),
+        };
+        let (file_id, new_content) = match created {
+            Ok(created) => created,
+            Err(e) => {
+                file.discard().await;
+                return Err(e.into());
+            }
+        };
+        self.store_content(file, &file_id, &sha256, new_content)
+            .await?;
+
+        Ok(sha256)
+    }
+
+    /// Writes the content of a file that was just added to the store, unless the same
+    /// content is already stored, marks it as stored and queues it for text extraction and
+    /// preview generation. The file is removed again if that fails.
+    async fn store_content(
+        &self,
+        file: PendingFile,
+        file_id: &str,
+        sha256: &str,
+        new_content: bool,
+    ) -> Result<(), Status> {
+        // Content that is already stored is shared; the staged copy is discarded.
+        if !new_content {
+            info!("File content already stored, reusing {}", sha256);
+            file.discard().await;
+            return Ok(());
+        }
+
+        let stored = match file
+            .persist(self.store.as_ref(), &file_store::blob_key(sha256))
+            .await
+        {
+            Ok(()) => sql_operations::mark_content_stored(sha256.to_string()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = stored {
+            let rollback = sql_operations::delete_post_by_file_uuid(file_id.to_string()).await;
+            if let Err(rollback_error) = rollback {
+                error!(
+                    "Failed to roll back post after upload error: {}",
+                    rollback_error
+                );
+            }
+            return Err(e.into());
+        }
+        self.extractor.notify();
+        self.previews.notify();
+
+        Ok(())
+    }
+
+    /// Removes content no file refers to any more, unless an upload of the same content has
+    /// taken it up again in the meantime. Failures are left to the sweeper.
+    async fn release_content(&self, key: Option<String>) {
+        if let Some(key) = key {
+            if let Err(e) = storage::delete_released(&self.store, &key).await {
+                error!("Failed to remove released file {}: {}", key, e);
+            }
+        }
+    }
+
+    async fn session_status(&self, session: &UploadSession) -> Result<UploadSessionStatus, Status> {
+        Ok(UploadSessionStatus {
+            session_id: session.session_id.clone(),
+            received_bytes: storage::session_received_bytes(&session.session_id).await?,
+            total_size: session.total_size,
+            expires_at: session.expires_at.clone(),
+        })
+    }
+
+    /// Creates the post of a fully received upload session and stores its content. The
+    /// session is only removed once its content is stored, so a commit that fails can be
+    /// retried. Returns the content's SHA-256.
+    async fn commit_session(&self, session: &UploadSession) -> Result<String, Status> {
+        let _active = self.sessions.claim(&session.session_id)?;
+
+        let file = PendingFile::from_session(&session.session_id).await?;
+        if file.size() != session.total_size {
+            return Err(Status::failed_precondition(format!(
+                "Only {} of {} bytes have been received.",
+                file.size(),
+                session.total_size
+            )));
+        }
+
+        let metadata = session_metadata(session);
+        let extension = upload::validate_metadata(&metadata)?;
+        let session_id = Some(session.session_id.clone());
+        let sha256 = self
+            .store_upload(session.user_id, metadata, &extension, file, session_id)
+            .await?;
+
+        // The post is complete; a session left behind expires and is swept.
+        if let Err(e) = sql_operations::delete_upload_session(session.session_id.clone()).await {
+            error!(
+                "Failed to delete upload session {}: {}",
+                session.session_id, e
+            );
+        }
+        storage::remove_session_file(&session.session_id).await;
+
+        Ok(sha256)
+    }
+}
+
+fn new_file(uploader_id: u32, file_name: String, extension: &str, file: &PendingFile) -> NewFile {
+    NewFile {
+        file_id: Uuid::new_v4().to_string(),
+        uploader_id,
+        file_name,
+        size: file.size(),
+        sha256: file.sha256(),
+        mime_type: upload::mime_type(file.head(), extension),
+    }
+}
+
+fn post_query(request: ChannelRequest) -> AppResult<PostQuery> {
+    let order = match ProtoPostOrder::try_from(request.order) {
+        Ok(ProtoPostOrder::Newest) => PostOrder::Newest,
+        Ok(ProtoPostOrder::Oldest) => PostOrder::Oldest,
+        Ok(ProtoPostOrder::HighestRated) => PostOrder::HighestRated,
+        Ok(ProtoPostOrder::LowestRated) => PostOrder::LowestRated,
+        Err(_) => return Err(AppError::Validation("Unknown post order.".to_string())),
+    };
+    let non_empty = |value: String| (!value.trim().is_empty()).then_some(value);
+
+    let mut query = PostQuery {
+        order,
+        page_size: listing::page_size(request.page_size),
+        published_from: listing::parse_date(&request.published_from)?,
+        published_to: listing::parse_date(&request.published_to)?,
+        file_type: non_empty(request.file_type),
+        title_contains: non_empty(request.title_contains),
+        ..PostQuery::new(request.channel_id)
+    };
+    if !request.page_token.is_empty() {
+        query.after = Some(PageCursor::from_token(
+            &request.page_token,
+            &query.listing(),
+        )?);
+    }
+
+    Ok(query)
+}
+
+fn post_info(post: Post) -> PostInfo {
+    PostInfo {
+        post_id: post.post_id,
+        channel_id: post.channel_id,
+        file_id: post.file_id,
+        title: post.title,
+        description: post.description,
+        publish_date: post.publish_date,
+        average_rating: post.average_rating,
+    }
+}
+
+fn feed_post_info(feed_post: FeedPost) -> FeedPostInfo {
+    FeedPostInfo {
+        post: Some(post_info(feed_post.post)),
+        channel_name: feed_post.channel_name,
+    }
+}
+
+fn session_metadata(session: &UploadSession) -> UploadMetadata {
+    UploadMetadata {
+        filename: session.file_name.clone(),
+        channel_id: session.channel_id,
+        title: session.title.clone(),
+        description: session.description.clone(),
+    }
+}
 
 #[tonic::async_trait]
 impl PostsService for PostsServicesStruct {
@@ -26,106 +271,111 @@ impl PostsService for PostsServicesStruct {
     ) -> Result<Response<UploadStatusResponse>, Status> {
         info!("Received request to upload post");
 
+        let user = authentication::caller(request.extensions()).await?;
+        user.ensure_role(&[Role::Professor])?;
         let mut stream = request.into_inner();
-        let uuid: String = Uuid::new_v4().to_string();
-        let mut channel_id = None;
-        let mut file_name = None;
-        let mut title = None;
-        let mut description = None;
-        let mut file = None;
-
-        while let Some(file_chunk) = stream.next().await {
-            let file_chunk = file_chunk?;
-
-            if channel_id.is_none() {
-                channel_id = Some(file_chunk.channel_id);
-                file_name = Some(file_chunk.filename);
-                title = Some(file_chunk.title);
-                description = Some(file_chunk.description);
-
-                let channel_path = format!(
-                    "{}/{}",
-                    std::env::var("FILE_DIR")
-                        .expect("Couldn't get file directory from cargo environment"),
-                    channel_id.as_ref().unwrap()
-                );
-                tokio::fs::create_dir_all(&channel_path)
-                    .await
-                    .map_err(|e| {
-                        error!("Failed to create directory: {:?}", e);
-                        Status::internal("Failed to create directory")
-                    })?;
-
-                let extension = file_name.as_ref().unwrap().split('.').last().unwrap();
-                let file_path = format!("{}/{}.{}", channel_path, uuid, extension);
 
-                file = Some(tokio::fs::File::create(file_path).await.map_err(|e| {
-                    error!("Failed to create file: {:?}", e);
-                    Status::internal("Failed to create file")
-                })?);
+        let metadata = match stream
+            .next()
+            .await
+            .transpose()?
+            .and_then(|chunk| chunk.data)
+        {
+            Some(Data::Metadata(metadata)) => metadata,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "The first message must carry the upload metadata.",
+                ))
             }
+        };
+        let extension = upload::validate_metadata(&metadata)?;
+        let creator_id = sql_operations::get_channel_creator(metadata.channel_id).await?;
+        user.ensure_owner(creator_id)?;
+
+        let limits = sql_operations::get_quota_limits(user.user_id, metadata.channel_id).await?;
+        let usage = sql_operations::get_storage_usage(user.user_id, metadata.channel_id).await?;
+        limits.check(&usage, 0)?;
+
+        let mut file = PendingFile::create(&Uuid::new_v4().to_string()).await?;
+
+        let received: Result<(), Status> = async {
+            while let Some(file_chunk) = stream.next().await {
+                let content = match file_chunk?.data {
+                    Some(Data::Content(content)) => content,
+                    _ => {
+                        return Err(Status::invalid_argument(
+                            "Only content chunks may follow the upload metadata.",
+                        ))
+                    }
+                };
 
-            if let Some(ref mut f) = file {
-                f.write_all(&file_chunk.content).await.map_err(|e| {
-                    error!("Failed to write file data: {:?}", e);
-                    Status::internal("Failed to write file data")
-                })?;
+                limits.check(&usage, file.size() + content.len() as u64)?;
+                file.write(&content).await?;
             }
+            Ok(())
         }
-
-        let sql_result = sql_operations::create_post(
-            uuid.clone(),
-            channel_id.unwrap(),
-            file_name.clone().unwrap(),
-            title.unwrap(),
-            description.unwrap(),
-        )
         .await;
-
-        match sql_result {
-            Ok(result) => {
-                info!("File uploaded to server successfully");
-                Ok(Response::new(UploadStatusResponse {
-                    success: result,
-                    message: "File uploaded successfully".to_string(),
-                }))
-            }
-            Err(e) => {
-                error!("Failed to insert file data: {:?}", e);
-                Ok(Response::new(UploadStatusResponse {
-                    success: false,
-                    message: "Failed to upload file".to_string(),
-                }))
-            }
+        if let Err(e) = received {
+            file.discard().await;
+            return Err(e);
         }
+
+        let sha256 = self
+            .store_upload(user.user_id, metadata, &extension, file, None)
+            .await?;
+
+        info!("File uploaded to server successfully");
+        Ok(Response::new(UploadStatusResponse {
+            success: true,
+            message: "File uploaded successfully".to_string(),
+            sha256,
+        }))
     }
 
     async fn get_posts_by_channel_id(
         &self,
         request: Request<ChannelRequest>,
     ) -> Result<Response<PostsResponse>, Status> {
-        let channel_id = request.into_inner().channel_id;
+        let user = authentication::caller(request.extensions()).await?;
+        let request = request.into_inner();
+        access::ensure_can_view(&user, request.channel_id).await?;
 
-        let result = sql_operations::get_posts_by_channel_id(channel_id).await;
+        let query = post_query(request)?;
+        let listing = query.listing();
+        let (posts, next) = sql_operations::get_posts_by_channel_id(query).await?;
 
-        if result.is_err() {
-            return Err(Status::internal("Failed to get posts"));
-        }
+        let post_infos: Vec<PostInfo> = posts.into_iter().map(post_info).collect();
 
-        let post_infos: Vec<PostInfo> = result
-            .unwrap()
-            .into_iter()
-            .map(|post| PostInfo {
-                post_id: post.post_id,
-                channel_id: post.channel_id,
-                file_id: post.file_id,
-                title: post.title,
-                description: post.description,
-                publish_date: post.publish_date,
-            })
-            .collect();
-
-        let response = PostsResponse { posts: post_infos };
+        let response = PostsResponse {
+            posts: post_infos,
+            next_page_token: next
+                .map(|cursor| cursor.to_token(&listing))
+                .unwrap_or_default(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_feed(
+        &self,
+        request: Request<FeedRequest>,
+    ) -> Result<Response<FeedResponse>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        let request = request.into_inner();
+        let after = match request.page_token.as_str() {
+            "" => None,
+            token => Some(PageCursor::from_token(token, listing::FEED_LISTING)?),
+        };
+
+        let page_size = listing::page_size(request.page_size);
+        let (posts, next) = sql_operations::get_feed(user.user_id, page_size, after).await?;
+
+        let response = FeedResponse {
+            posts: posts.into_iter().map(feed_post_info).collect(),
+            next_page_token: next
+                .map(|cursor| cursor.to_token(listing::FEED_LISTING))
+                .unwrap_or_default(),
+        };
 
         Ok(Response::new(response))
     }
@@ -134,17 +384,53 @@ impl PostsService for PostsServicesStruct {
         &self,
         request: Request<FileId>,
     ) -> Result<Response<FileName>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
         let file_id = request.into_inner().file_id;
+        let channel_id = sql_operations::get_file_channel(file_id.clone()).await?;
+        access::ensure_can_view(&user, channel_id).await?;
 
-        let result = sql_operations::get_file_name(file_id).await;
+        let name = sql_operations::get_file_name(file_id).await?;
 
-        if result.is_err() {
-            return Err(Status::internal("Failed to get file name"));
-        }
+        let response = FileName { filename: name };
 
-        let name = result.unwrap();
+        Ok(Response::new(response))
+    }
 
-        let response = FileName { filename: name };
+    async fn get_preview(
+        &self,
+        request: Request<FileId>,
+    ) -> Result<Response<PreviewResponse>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        let file_id = request.into_inner().file_id;
+        let stored_file = sql_operations::get_stored_file(file_id).await?;
+        access::ensure_can_view(&user, stored_file.channel_id).await?;
+
+        let unavailable = PreviewResponse {
+            status: ProtoPreviewStatus::PreviewUnavailable.into(),
+            ..Default::default()
+        };
+        // Files uploaded before hashing have no previews.
+        let Some(sha256) = stored_file.sha256 else {
+            return Ok(Response::new(unavailable));
+        };
+
+        let response = match sql_operations::get_preview(sha256.clone()).await? {
+            None => PreviewResponse {
+                status: ProtoPreviewStatus::PreviewPending.into(),
+                ..Default::default()
+            },
+            Some(preview) if preview.status == PreviewStatus::Generated => {
+                let key = file_store::preview_key(&sha256);
+                PreviewResponse {
+                    status: ProtoPreviewStatus::PreviewReady.into(),
+                    content: file_store::read_all(self.store.as_ref(), &key).await?,
+                    mime_type: previews::PREVIEW_MIME_TYPE.to_string(),
+                    width: preview.width,
+                    height: preview.height,
+                }
+            }
+            Some(_) => unavailable,
+        };
 
         Ok(Response::new(response))
     }
@@ -155,51 +441,62 @@ impl PostsService for PostsServicesStruct {
         &self,
         request: Request<FileDownloadRequest>,
     ) -> Result<Response<Self::DownloadFileStream>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
         let request = request.into_inner();
-        let file_id = request.file_id.clone();
-        let channel_id = request.channel_id;
-
-        let file_name = sql_operations::get_file_name(file_id.clone())
-            .await
-            .map_err(|e| {
-                error!("Failed to get file name: {:?}", e);
-                Status::internal("Failed to get file name")
-            })?;
-
-        let extension = file_name.split('.').last().unwrap_or("");
-
-        let file_path = format!(
-            "{}/{}/{}.{}",
-            std::env::var("FILE_DIR").expect("Couldn't get file directory from cargo env"),
-            channel_id,
-            file_id,
-            extension
-        );
+        let stored_file = sql_operations::get_stored_file(request.file_id.clone()).await?;
+        access::ensure_can_view(&user, stored_file.channel_id).await?;
+
+        let range = ByteRange::resolve(request.offset, request.length, stored_file.size)?;
+        let length = range.length.unwrap_or_default();
+        let mut file = match length {
+            0 => None,
+            _ => Some(self.store.get(&stored_file.key(), range).await?),
+        };
 
-        let mut file = File::open(&file_path).await.map_err(|e| {
-            error!("Failed to open file: {:?}", e);
-            Status::not_found("File not found")
-        })?;
+        let file_id = request.file_id;
+        let info = DownloadInfo {
+            total_size: stored_file.size,
+            sha256: stored_file.sha256.unwrap_or_default(),
+            mime_type: stored_file.mime_type.unwrap_or_default(),
+            offset: range.offset,
+            length,
+        };
 
         let file_stream = try_stream! {
-            let mut buffer = vec![0; 1024];
+            yield FileData {
+                content: Vec::new(),
+                filename: file_id.clone(),
+                info: Some(info),
+            };
+
+            let Some(file) = file.as_mut() else {
+                return;
+            };
+            let mut buffer = vec![0; DOWNLOAD_CHUNK_SIZE];
 
             loop {
-                let bytes_read = file.read(&mut buffer).await.map_err(|e| {
-                    error!("Failed to read file: {:?}", e);
-                    Status::internal("Failed to read file")
-                })?;
+                // Fill whole chunks; the store may hand out much smaller reads.
+                let mut filled = 0;
+                while filled < buffer.len() {
+                    let bytes_read = file.read(&mut buffer[filled..]).await.map_err(|e| {
+                        error!("Failed to read file: {:?}", e);
+                        Status::internal("Failed to read file")
+                    })?;
+                    if bytes_read == 0 {
+                        break;
+                    }
+                    filled += bytes_read;
+                }
 
-                if bytes_read == 0 {
+                if filled == 0 {
                     break;
                 }
 
-                let chunk = FileData {
-                    content: buffer[..bytes_read].to_vec(),
+                yield FileData {
+                    content: buffer[..filled].to_vec(),
                     filename: file_id.clone(),
+                    info: None,
                 };
-
-                yield chunk;
             }
         };
 
@@ -207,4 +504,433 @@ impl PostsService for PostsServicesStruct {
             Box::pin(file_stream) as Self::DownloadFileStream
         ))
     }
+
+    async fn get_storage_usage(
+        &self,
+        request: Request<StorageUsageRequest>,
+    ) -> Result<Response<StorageUsageResponse>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        let channel_id = request.into_inner().channel_id;
+
+        if channel_id != 0 {
+            let creator_id = sql_operations::get_channel_creator(channel_id).await?;
+            user.ensure_owner(creator_id)?;
+        }
+
+        let limits = sql_operations::get_quota_limits(user.user_id, channel_id).await?;
+        let usage = sql_operations::get_storage_usage(user.user_id, channel_id).await?;
+
+        Ok(Response::new(StorageUsageResponse {
+            user_used_bytes: usage.user_bytes,
+            user_quota_bytes: limits.user_quota,
+            channel_used_bytes: usage.channel_bytes,
+            channel_quota_bytes: limits.channel_quota,
+            max_file_size_bytes: limits.max_file_size,
+        }))
+    }
+
+    async fn create_upload_session(
+        &self,
+        request: Request<CreateUploadSessionRequest>,
+    ) -> Result<Response<UploadSessionStatus>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        user.ensure_role(&[Role::Professor])?;
+        let request = request.into_inner();
+        let metadata = request
+            .metadata
+            .ok_or_else(|| Status::invalid_argument("The upload metadata is required."))?;
+
+        upload::validate_metadata(&metadata)?;
+        let creator_id = sql_operations::get_channel_creator(metadata.channel_id).await?;
+        user.ensure_owner(creator_id)?;
+
+        let limits = sql_operations::get_quota_limits(user.user_id, metadata.channel_id).await?;
+        let usage = sql_operations::get_storage_usage(user.user_id, metadata.channel_id).await?;
+        limits.check(&usage, request.total_size)?;
+
+        let session_id = Uuid::new_v4().to_string();
+        storage::create_session_file(&session_id).await?;
+        let session = UploadSession {
+            session_id: session_id.clone(),
+            user_id: user.user_id,
+            channel_id: metadata.channel_id,
+            file_name: metadata.filename,
+            title: metadata.title,
+            description: metadata.description,
+            total_size: request.total_size,
+            expires_at: String::new(),
+        };
+        let ttl_secs = upload_session::session_ttl().as_secs();
+        if let Err(e) = sql_operations::create_upload_session(session, ttl_secs).await {
+            storage::remove_session_file(&session_id).await;
+            return Err(e.into());
+        }
+
+        let session = sql_operations::get_upload_session(session_id).await?;
+        info!("Created upload session {}", session.session_id);
+        Ok(Response::new(self.session_status(&session).await?))
+    }
+
+    async fn upload_part(
+        &self,
+        request: Request<tonic::Streaming<UploadPartChunk>>,
+    ) -> Result<Response<UploadSessionStatus>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        let mut stream = request.into_inner();
+
+        let header = match stream
+            .next()
+            .await
+            .transpose()?
+            .and_then(|chunk| chunk.data)
+        {
+            Some(PartData::Header(header)) => header,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "The first message must carry the part header.",
+                ))
+            }
+        };
+        let session = sql_operations::get_upload_session(header.session_id).await?;
+        user.ensure_owner(session.user_id)?;
+        let _active = self.sessions.claim(&session.session_id)?;
+
+        let received = storage::session_received_bytes(&session.session_id).await?;
+        if header.offset > received {
+            return Err(Status::failed_precondition(format!(
+                "Only {} bytes have been received; the part can't start at {}.",
+                received, header.offset
+            )));
+        }
+
+        let mut file = storage::open_session_at(&session.session_id, header.offset).await?;
+        let mut received = header.offset;
+
+        // Whatever arrived is kept when the stream fails, so the client can continue.
+        let result: Result<(), Status> = async {
+            while let Some(chunk) = stream.next().await {
+                let content = match chunk?.data {
+                    Some(PartData::Content(content)) => content,
+                    _ => {
+                        return Err(Status::invalid_argument(
+                            "Only content chunks may follow the part header.",
+                        ))
+                    }
+                };
+
+                received += content.len() as u64;
+                if received > session.total_size {
+                    return Err(AppError::Validation(
+                        "The upload is larger than the size given for the session.".to_string(),
+                    )
+                    .into());
+                }
+                file.write_all(&content).await.map_err(|e| {
+                    error!("Failed to write upload session: {:?}", e);
+                    Status::internal("Failed to write file")
+                })?;
+            }
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = file.flush().await {
+            error!("Failed to flush upload session: {:?}", e);
+        }
+        result?;
+
+        Ok(Response::new(self.session_status(&session).await?))
+    }
+
+    async fn get_upload_session(
+        &self,
+        request: Request<UploadSessionRequest>,
+    ) -> Result<Response<UploadSessionStatus>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        let session_id = request.into_inner().session_id;
+        let session = sql_operations::get_upload_session(session_id).await?;
+        user.ensure_owner(session.user_id)?;
+
+        Ok(Response::new(self.session_status(&session).await?))
+    }
+
+    async fn commit_upload_session(
+        &self,
+        request: Request<UploadSessionRequest>,
+    ) -> Result<Response<UploadStatusResponse>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        let session_id = request.into_inner().session_id;
+        let session = sql_operations::get_upload_session(session_id).await?;
+        user.ensure_owner(session.user_id)?;
+        let sha256 = self.commit_session(&session).await?;
+
+        info!("Upload session {} committed", session.session_id);
+        Ok(Response::new(UploadStatusResponse {
+            success: true,
+            message: "File uploaded successfully".to_string(),
+            sha256,
+        }))
+    }
+
+    async fn get_post(&self, request: Request<PostRequest>) -> Result<Response<PostInfo>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        let post = sql_operations::get_post(request.into_inner().post_id).await?;
+        access::ensure_can_view(&user, post.channel_id).await?;
+
+        Ok(Response::new(post_info(post)))
+    }
+
+    async fn update_post(
+        &self,
+        request: Request<UpdatePostRequest>,
+    ) -> Result<Response<PostInfo>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        let request = request.into_inner();
+        let post = sql_operations::get_post(request.post_id).await?;
+        access::ensure_can_manage(&user, post.channel_id).await?;
+
+        upload::validate_post_text(&request.title, &request.description)?;
+        let target_channel = (request.channel_id != 0 && request.channel_id != post.channel_id)
+            .then_some(request.channel_id);
+        if let Some(channel_id) = target_channel {
+            access::ensure_can_manage(&user, channel_id).await?;
+        }
+
+        sql_operations::update_post(
+            post.post_id,
+            request.title,
+            request.description,
+            target_channel,
+            QuotaLimits::from_env(),
+        )
+        .await?;
+
+        let post = sql_operations::get_post(post.post_id).await?;
+        Ok(Response::new(post_info(post)))
+    }
+
+    async fn delete_post(
+        &self,
+        request: Request<PostRequest>,
+    ) -> Result<Response<DeletePostResponse>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        let post = sql_operations::get_post(request.into_inner().post_id).await?;
+        access::ensure_can_manage(&user, post.channel_id).await?;
+
+        let released = sql_operations::delete_post(post.post_id).await?;
+        self.release_content(released).await;
+
+        info!("Post {} deleted", post.post_id);
+        Ok(Response::new(DeletePostResponse {
+            success: true,
+            message: "Post deleted successfully".to_string(),
+        }))
+    }
+
+    async fn replace_post_file(
+        &self,
+        request: Request<tonic::Streaming<ReplaceFileChunk>>,
+    ) -> Result<Response<UploadStatusResponse>, Status> {
+        let user = authentication::caller(request.extensions()).await?;
+        user.ensure_role(&[Role::Professor])?;
+        let mut stream = request.into_inner();
+
+        let header = match stream
+            .next()
+            .await
+            .transpose()?
+            .and_then(|chunk| chunk.data)
+        {
+            Some(ReplaceData::Header(header)) => header,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "The first message must carry the replacement header.",
+                ))
+            }
+        };
+        let post = sql_operations::get_post(header.post_id).await?;
+        access::ensure_can_manage(&user, post.channel_id).await?;
+        let extension = upload::validate_file_name(&header.filename)?;
+
+        let old_file = sql_operations::get_stored_file(post.file_id.clone()).await?;
+        let limits = sql_operations::get_quota_limits(user.user_id, post.channel_id).await?;
+        let usage = sql_operations::get_storage_usage(user.user_id, post.channel_id)
+            .await?
+            .without_file(old_file.size, old_file.uploader_id == Some(user.user_id));
+        limits.check(&usage, 0)?;
+
+        let mut file = PendingFile::create(&Uuid::new_v4().to_string()).await?;
+
+        let received: Result<(), Status> = async {
+            while let Some(chunk) = stream.next().await {
+                let content = match chunk?.data {
+                    Some(ReplaceData::Content(content)) => content,
+                    _ => {
+                        return Err(Status::invalid_argument(
+                            "Only content chunks may follow the replacement header.",
+                        ))
+                    }
+                };
+
+                limits.check(&usage, file.size() + content.len() as u64)?;
+                file.write(&content).await?;
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = received {
+            file.discard().await;
+            return Err(e);
+        }
+
+        let new_file = new_file(user.user_id, header.filename, &extension, &file);
+        let (file_id, sha256) = (new_file.file_id.clone(), new_file.sha256.clone());
+        let added = sql_operations::add_replacement_file(
+            new_file,
+            post.channel_id,
+            post.file_id.clone(),
+            limits,
+        )
+        .await;
+        let new_content = match added {
+            Ok(new_content) => new_content,
+            Err(e) => {
+                file.discard().await;
+                return Err(e.into());
+            }
+        };
+        self.store_content(file, &file_id, &sha256, new_content)
+            .await?;
+
+        let released =
+            match sql_operations::replace_post_file(post.post_id, post.file_id, file_id.clone())
+                .await
+            {
+                Ok(released) => released,
+                Err(e) => {
+                    if let Err(rollback_error) =
+                        sql_operations::delete_post_by_file_uuid(file_id).await
+                    {
+                        error!(
+                            "Failed to remove replacement file after error: {}",
+                            rollback_error
+                        );
+                    }
+                    return Err(e.into());
+                }
+            };
+        self.release_content(released).await;
+
+        info!("File of post {} replaced", post.post_id);
+        Ok(Response::new(UploadStatusResponse {
+            success: true,
+            message: "File replaced successfully".to_string(),
+            sha256,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_store::{FileReader, StoredObject};
+    use crate::local_store::LocalFileStore;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// A store whose writes fail while `failing` is set.
+    struct FlakyStore {
+        inner: LocalFileStore,
+        failing: AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl FileStore for FlakyStore {
+        async fn put(&self, key: &str, source: &Path) -> AppResult<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(AppError::Internal("Store unavailable.".to_string()));
+            }
+            self.inner.put(key, source).await
+        }
+
+        async fn get(&self, key: &str, range: ByteRange) -> AppResult<FileReader> {
+            self.inner.get(key, range).await
+        }
+
+        async fn delete(&self, key: &str) -> AppResult<()> {
+            self.inner.delete(key).await
+        }
+
+        async fn list(&self) -> AppResult<Vec<StoredObject>> {
+            self.inner.list().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commit_session_retried_after_store_failure() {
+        let dir = std::env::temp_dir().join("posts-controller-test");
+        std::env::set_var("UPLOAD_TMP_DIR", &dir);
+        let store = Arc::new(FlakyStore {
+            inner: LocalFileStore::new(dir.join("store")),
+            failing: AtomicBool::new(true),
+        });
+        let service = PostsServicesStruct::new(
+            store.clone(),
+            TextExtractor::default(),
+            PreviewGenerator::default(),
+        );
+
+        let session_id = Uuid::new_v4().to_string();
+        let content = format!("notes {}", session_id);
+        storage::create_session_file(&session_id).await.unwrap();
+        let mut file = storage::open_session_at(&session_id, 0).await.unwrap();
+        file.write_all(content.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+        let title = format!("Retried {}", &session_id[..8]);
+        let session = UploadSession {
+            session_id: session_id.clone(),
+            user_id: 2,
+            channel_id: 1,
+            file_name: "notes.txt".to_string(),
+            title: title.clone(),
+            description: String::new(),
+            total_size: content.len() as u64,
+            expires_at: String::new(),
+        };
+        sql_operations::create_upload_session(session, 3600)
+            .await
+            .unwrap();
+        let session = sql_operations::get_upload_session(session_id.clone())
+            .await
+            .unwrap();
+
+        assert!(service.commit_session(&session).await.is_err());
+        assert!(sql_operations::get_upload_session(session_id.clone())
+            .await
+            .is_ok());
+
+        store.failing.store(false, Ordering::SeqCst);
+        let sha256 = service.commit_session(&session).await.unwrap();
+        let mut stored = Vec::new();
+        store
+            .get(&file_store::blob_key(&sha256), ByteRange::default())
+            .await
+            .unwrap()
+            .read_to_end(&mut stored)
+            .await
+            .unwrap();
+        assert_eq!(stored, content.as_bytes());
+        assert!(matches!(
+            sql_operations::get_upload_session(session_id.clone()).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        // post
+        let (posts, _) = sql_operations::get_posts_by_channel_id(PostQuery::new(1))
+            .await
+            .unwrap();
+        let posts: Vec<_> = posts.into_iter().filter(|p| p.title == title).collect();
+        assert_eq!(posts.len(), 1);
+        let _ = sql_operations::delete_post_by_file_uuid(posts[0].file_id.clone()).await;
+    }
 }
@@ -0,0 +1,238 @@
+use crate::post::Post;
+use chrono::NaiveDate;
+use data_access::{AppError, AppResult};
+use sha2::{Digest, Sha256};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+/// The listing page tokens of the feed belong to, see [`PageCursor::to_token`].
+pub const FEED_LISTING: &str = "feed";
+
+/// Order of a post listing. Ties are broken by post id in the same direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostOrder {
+    Newest,
+    Oldest,
+    HighestRated,
+    LowestRated,
+}
+
+impl PostOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PostOrder::Newest => "newest",
+            PostOrder::Oldest => "oldest",
+            PostOrder::HighestRated => "highest_rated",
+            PostOrder::LowestRated => "lowest_rated",
+        }
+    }
+
+    /// The column of the listing query the posts are sorted by.
+    pub fn sort_column(&self) -> &'static str {
+        match self {
+            PostOrder::Newest | PostOrder::Oldest => "publish_date",
+            PostOrder::HighestRated | PostOrder::LowestRated => "average_rating",
+        }
+    }
+
+    pub fn descending(&self) -> bool {
+        matches!(self, PostOrder::Newest | PostOrder::HighestRated)
+    }
+
+    /// The cursor of a page ending with `post`.
+    pub fn cursor(&self, post: &Post) -> PageCursor {
+        let sort_key = match self {
+            PostOrder::Newest | PostOrder::Oldest => post.publish_date.clone(),
+            PostOrder::HighestRated | PostOrder::LowestRated => post.average_rating.to_string(),
+        };
+
+        PageCursor {
+            sort_key,
+            post_id: post.post_id,
+        }
+    }
+}
+
+/// Where a page ends: the sort value and id of its last post.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageCursor {
+    pub sort_key: String,
+    pub post_id: u32,
+}
+
+impl PageCursor {
+    /// Encodes the cursor as an opaque page token, tied to the `listing` it was listed in,
+    /// see [`PostQuery::listing`].
+    pub fn to_token(&self, listing: &str) -> String {
+        hex::encode(format!("{}|{}|{}", listing, self.post_id, self.sort_key))
+    }
+
+    pub fn from_token(token: &str, listing: &str) -> AppResult<Self> {
+        let invalid = || AppError::Validation("Invalid page token.".to_string());
+
+        let decoded = hex::decode(token).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let mut parts = decoded.splitn(3, '|');
+
+        if parts.next() != Some(listing) {
+            return Err(AppError::Validation(
+                "The page token belongs to another listing.".to_string(),
+            ));
+        }
+        let post_id = parts
+            .next()
+            .and_then(|post_id| post_id.parse().ok())
+            .ok_or_else(invalid)?;
+        let sort_key = parts.next().ok_or_else(invalid)?.to_string();
+
+        Ok(PageCursor { sort_key, post_id })
+    }
+}
+
+/// Which posts of a channel to list.
+#[derive(Debug, Clone)]
+pub struct PostQuery {
+    pub channel_id: u32,
+    pub order: PostOrder,
+    pub page_size: u32,
+    pub after: Option<PageCursor>,
+    pub published_from: Option<NaiveDate>,
+    pub published_to: Option<NaiveDate>,
+    pub file_type: Option<String>,
+    pub title_contains: Option<String>,
+}
+
+impl PostQuery {
+    /// The first page of the channel's newest posts, without filters.
+    pub fn new(channel_id: u32) -> Self {
+        PostQuery {
+            channel_id,
+            order: PostOrder::Newest,
+            page_size: DEFAULT_PAGE_SIZE,
+            after: None,
+            published_from: None,
+            published_to: None,
+            file_type: None,
+            title_contains: None,
+        }
+    }
+
+    /// Identifies the listing for its page tokens: the channel, the order and a hash of the
+    /// filters, so that a token can't be used with other ones.
+    pub fn listing(&self) -> String {
+        let filters = format!(
+            "{:?}|{:?}|{:?}|{:?}",
+            self.published_from, self.published_to, self.file_type, self.title_contains
+        );
+        let hash = Sha256::digest(filters);
+
+        format!(
+            "{}:{}:{}",
+            self.order.as_str(),
+            self.channel_id,
+            hex::encode(&hash[..8])
+        )
+    }
+}
+
+/// The requested page size, defaulting to 50 and capped at 200.
+pub fn page_size(requested: u32) -> u32 {
+    match requested {
+        0 => DEFAULT_PAGE_SIZE,
+        size => size.min(MAX_PAGE_SIZE),
+    }
+}
+
+/// Parses an optional `YYYY-MM-DD` date filter; empty means no filter.
+pub fn parse_date(value: &str) -> AppResult<Option<NaiveDate>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(Some)
+        .map_err(|_| AppError::Validation(format!("Invalid date \"{}\", use YYYY-MM-DD.", value)))
+}
+
+/// Escapes `%`, `_` and `\` so the text only matches itself in a `LIKE` pattern.
+pub fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_token_round_trip() {
+        let cursor = PageCursor {
+            sort_key: "3.5000".to_string(),
+            post_id: 42,
+        };
+        let query = PostQuery {
+            order: PostOrder::HighestRated,
+            ..PostQuery::new(1)
+        };
+        let token = cursor.to_token(&query.listing());
+
+        let decoded = PageCursor::from_token(&token, &query.listing()).unwrap();
+        assert_eq!(decoded, cursor);
+
+        let result = PageCursor::from_token(&token, &PostQuery::new(1).listing());
+        assert!(matches!(result, Err(AppError::Validation(_))));
+
+        let result = PageCursor::from_token("not a token", &query.listing());
+        assert!(matches!(result, Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn test_listing() {
+        let query = PostQuery::new(1);
+        assert_eq!(query.listing(), PostQuery::new(1).listing());
+        assert_ne!(query.listing(), PostQuery::new(2).listing());
+
+        let filtered = PostQuery {
+            file_type: Some("pdf".to_string()),
+            ..PostQuery::new(1)
+        };
+        assert_ne!(query.listing(), filtered.listing());
+
+        let titled = PostQuery {
+            title_contains: Some("pdf".to_string()),
+            ..PostQuery::new(1)
+        };
+        assert_ne!(filtered.listing(), titled.listing());
+    }
+
+    #[test]
+    fn test_page_size() {
+        assert_eq!(page_size(0), 50);
+        assert_eq!(page_size(10), 10);
+        assert_eq!(page_size(1000), 200);
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("").unwrap(), None);
+        assert_eq!(
+            parse_date("2024-05-01").unwrap(),
+            NaiveDate::from_ymd_opt(2024, 5, 1)
+        );
+        assert!(matches!(
+            parse_date("01/05/2024"),
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("50%_off\\"), "50\\%\\_off\\\\");
+    }
+}
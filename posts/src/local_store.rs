@@ -0,0 +1,177 @@
+use crate::file_store::{ByteRange, FileReader, FileStore, StoredObject};
+use async_trait::async_trait;
+use data_access::{AppError, AppResult};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+use uuid::Uuid;
+
+/// Keeps files on the local filesystem at `{root}/{key}`.
+pub struct LocalFileStore {
+    root: PathBuf,
+}
+
+impl LocalFileStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalFileStore { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+fn io_error(action: &str, path: &Path, e: std::io::Error) -> AppError {
+    AppError::Internal(format!("Failed to {} {:?}: {}", action, path, e))
+}
+
+#[async_trait]
+impl FileStore for LocalFileStore {
+    async fn put(&self, key: &str, source: &Path) -> AppResult<()> {
+        let destination = self.path(key);
+        if let Some(parent) = destination.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| io_error("create directory", parent, e))?;
+        }
+
+        // Renaming fails across filesystems, e.g. when uploads are staged on a tmpfs. The
+        // copy is renamed into place, so that neither readers nor a concurrent upload of the
+        // same content see it half written.
+        if tokio::fs::rename(source, &destination).await.is_err() {
+            let partial = destination.with_file_name(format!(".{}.part", Uuid::new_v4()));
+            tokio::fs::copy(source, &partial)
+                .await
+                .map_err(|e| io_error("copy", source, e))?;
+            tokio::fs::rename(&partial, &destination)
+                .await
+                .map_err(|e| io_error("rename", &partial, e))?;
+        }
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, range: ByteRange) -> AppResult<FileReader> {
+        let path = self.path(key);
+        let mut file = match tokio::fs::File::open(&path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(AppError::NotFound("File not found.".to_string()))
+            }
+            Err(e) => return Err(io_error("open", &path, e)),
+        };
+
+        if range.offset > 0 {
+            file.seek(SeekFrom::Start(range.offset))
+                .await
+                .map_err(|e| io_error("seek in", &path, e))?;
+        }
+
+        match range.length {
+            Some(length) => Ok(Box::pin(file.take(length))),
+            None => Ok(Box::pin(file)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> AppResult<()> {
+        let path = self.path(key);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(io_error("remove", &path, e)),
+        }
+    }
+
+    async fn list(&self) -> AppResult<Vec<StoredObject>> {
+        let mut objects = Vec::new();
+
+        let mut channels = match tokio::fs::read_dir(&self.root).await {
+            Ok(channels) => channels,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(objects),
+            Err(e) => return Err(io_error("read", &self.root, e)),
+        };
+
+        while let Some(dir) = channels
+            .next_entry()
+            .await
+            .map_err(|e| io_error("read", &self.root, e))?
+        {
+            let dir_name = dir.file_name().to_string_lossy().into_owned();
+            if dir_name.starts_with('.') || !dir.file_type().await.is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+
+            let mut entries = tokio::fs::read_dir(dir.path())
+                .await
+                .map_err(|e| io_error("read", &dir.path(), e))?;
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(|e| io_error("read", &dir.path(), e))?
+            {
+                let Ok(metadata) = entry.metadata().await else {
+                    continue;
+                };
+                if !metadata.is_file() {
+                    continue;
+                }
+
+                objects.push(StoredObject {
+                    key: format!("{}/{}", dir_name, entry.file_name().to_string_lossy()),
+                    modified: metadata
+                        .modified()
+                        .map_err(|e| io_error("stat", &entry.path(), e))?,
+                });
+            }
+        }
+
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_store_round_trip() {
+        let root = std::env::temp_dir().join(format!("local-store-{}", std::process::id()));
+        let store = LocalFileStore::new(&root);
+        let source = std::env::temp_dir().join(format!("local-store-{}.part", std::process::id()));
+        std::fs::write(&source, b"content").unwrap();
+
+        store.put("1/file.txt", &source).await.unwrap();
+
+        let mut content = Vec::new();
+        let mut reader = store.get("1/file.txt", ByteRange::default()).await.unwrap();
+        reader.read_to_end(&mut content).await.unwrap();
+        assert_eq!(content, b"content");
+
+        let range = ByteRange {
+            offset: 2,
+            length: Some(3),
+        };
+        let mut content = Vec::new();
+        let mut reader = store.get("1/file.txt", range).await.unwrap();
+        reader.read_to_end(&mut content).await.unwrap();
+        assert_eq!(content, b"nte");
+
+        let keys: Vec<String> = store
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|o| o.key)
+            .collect();
+        assert_eq!(keys, vec!["1/file.txt".to_string()]);
+
+        store.delete("1/file.txt").await.unwrap();
+        assert!(matches!(
+            store.get("1/file.txt", ByteRange::default()).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        let _ = std::fs::remove_file(&source);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
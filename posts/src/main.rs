@@ -1,10 +1,24 @@
+mod access;
+mod authentication;
+mod file_store;
 mod grpc_controller;
+mod listing;
+mod local_store;
 mod post;
+mod previews;
+mod quota;
+mod s3_store;
 mod sql_operations;
+mod storage;
+mod text_extraction;
+mod upload;
+mod upload_session;
 
 use grpc_controller::PostsServicesStruct;
 use log::info;
 use posts::posts_service_server::PostsServiceServer;
+use previews::PreviewGenerator;
+use text_extraction::TextExtractor;
 use tonic::transport::Server;
 
 pub mod posts {
@@ -14,11 +28,21 @@ pub mod posts {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
+    data_access::get_pool()?;
+    let store = file_store::from_env()?;
+    tokio::spawn(storage::run_sweeper(store.clone()));
+    let extractor = TextExtractor::default();
+    tokio::spawn(extractor.clone().run(store.clone()));
+    let previews = PreviewGenerator::default();
+    tokio::spawn(previews.clone().run(store.clone()));
     let addr = "0.0.0.0:8081".parse()?;
-    let file_service = PostsServicesStruct;
+    let file_service = PostsServicesStruct::new(store, extractor, previews);
     info!("gRPC Server listening on {}", addr);
     Server::builder()
-        .add_service(PostsServiceServer::new(file_service))
+        .add_service(PostsServiceServer::with_interceptor(
+            file_service,
+            authentication::intercept,
+        ))
         .serve(addr)
         .await?;
     Ok(())
@@ -1,3 +1,4 @@
+use crate::file_store;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -8,4 +9,71 @@ pub struct Post {
     pub title: String,
     pub description: String,
     pub publish_date: String,
+    pub average_rating: f64,
+}
+
+/// A post of a user's feed, with the name of its channel.
+pub struct FeedPost {
+    pub post: Post,
+    pub channel_name: String,
+}
+
+pub struct StoredFile {
+    pub file_id: String,
+    pub channel_id: u32,
+    pub name: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub mime_type: Option<String>,
+    pub uploader_id: Option<u32>,
+}
+
+impl StoredFile {
+    /// Where the file's content is kept in the [`file_store::FileStore`].
+    pub fn key(&self) -> String {
+        match &self.sha256 {
+            Some(sha256) => file_store::blob_key(sha256),
+            None => {
+                let extension = self.name.rsplit('.').next().unwrap_or("");
+                file_store::file_key(self.channel_id, &self.file_id, extension)
+            }
+        }
+    }
+}
+
+/// An uploaded file about to be added.
+pub struct NewFile {
+    pub file_id: String,
+    pub uploader_id: u32,
+    pub file_name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub mime_type: String,
+}
+
+/// A post about to be created together with its uploaded file.
+pub struct NewPost {
+    pub channel_id: u32,
+    pub title: String,
+    pub description: String,
+    pub file: NewFile,
+}
+
+/// A resumable upload whose content is staged until it is committed as a post.
+pub struct UploadSession {
+    pub session_id: String,
+    pub user_id: u32,
+    pub channel_id: u32,
+    pub file_name: String,
+    pub title: String,
+    pub description: String,
+    pub total_size: u64,
+    pub expires_at: String,
+}
+
+/// Stored content a background job hasn't processed yet, with the type of a file using it.
+pub struct PendingContent {
+    pub sha256: String,
+    pub size: u64,
+    pub mime_type: String,
 }
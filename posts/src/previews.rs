@@ -0,0 +1,287 @@
+use crate::file_store::{self, FileStore};
+use crate::sql_operations;
+use crate::storage::{self, env_secs};
+use data_access::{AppError, AppResult};
+use image::{DynamicImage, ImageFormat};
+use log::{error, info, warn};
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Notify;
+
+pub const PREVIEW_MIME_TYPE: &str = "image/png";
+/// Previews fit in a square of this many pixels.
+const PREVIEW_SIZE: u32 = 320;
+const PDF: &str = "application/pdf";
+const IMAGE_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+const RENDER_TIMEOUT: Duration = Duration::from_secs(60);
+const BATCH_SIZE: u32 = 10;
+
+/// Outcome of the preview generation of some content, as stored in `blob_previews.status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewStatus {
+    Generated,
+    Unsupported,
+    TooLarge,
+    Failed,
+}
+
+impl PreviewStatus {
+    pub const ALL: [PreviewStatus; 4] = [
+        PreviewStatus::Generated,
+        PreviewStatus::Unsupported,
+        PreviewStatus::TooLarge,
+        PreviewStatus::Failed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PreviewStatus::Generated => "generated",
+            PreviewStatus::Unsupported => "unsupported",
+            PreviewStatus::TooLarge => "too_large",
+            PreviewStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(value: &str) -> AppResult<Self> {
+        PreviewStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == value)
+            .ok_or_else(|| AppError::Internal(format!("Unknown preview status \"{}\"", value)))
+    }
+}
+
+/// A generated preview, or why there is none.
+pub struct StoredPreview {
+    pub status: PreviewStatus,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A PNG preview ready to be stored.
+pub struct Preview {
+    pub png: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub fn is_supported(mime_type: &str) -> bool {
+    mime_type == PDF || IMAGE_TYPES.contains(&mime_type)
+}
+
+/// Scales the image down to fit [`PREVIEW_SIZE`], keeping its aspect ratio, and encodes it
+/// as PNG. Smaller images keep their size.
+pub fn image_preview(content: &[u8]) -> AppResult<Preview> {
+    let image = image::load_from_memory(content)
+        .map_err(|e| AppError::Validation(format!("Unreadable image: {}", e)))?;
+    let image = if image.width() > PREVIEW_SIZE || image.height() > PREVIEW_SIZE {
+        image.thumbnail(PREVIEW_SIZE, PREVIEW_SIZE)
+    } else {
+        image
+    };
+
+    encode_png(&image)
+}
+
+fn encode_png(image: &DynamicImage) -> AppResult<Preview> {
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, ImageFormat::Png)
+        .map_err(|e| AppError::Internal(format!("Failed to encode preview: {}", e)))?;
+
+    Ok(Preview {
+        png: png.into_inner(),
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+/// Renders the first page of the PDF at `source` with `PDF_RENDERER` (poppler's
+/// `pdftoppm` by default), scaled to fit [`PREVIEW_SIZE`].
+async fn render_pdf(source: &Path) -> AppResult<Preview> {
+    let renderer = std::env::var("PDF_RENDERER").unwrap_or_else(|_| "pdftoppm".to_string());
+    // pdftoppm adds ".png" to the output prefix it is given.
+    let prefix = source.with_extension("page");
+    let page = source.with_extension("page.png");
+    let size = PREVIEW_SIZE.to_string();
+
+    let mut command = Command::new(&renderer);
+    command
+        .args(["-png", "-singlefile", "-f", "1", "-l", "1"])
+        .args(["-scale-to", &size])
+        .arg(source)
+        .arg(&prefix)
+        .kill_on_drop(true);
+    let result = tokio::time::timeout(RENDER_TIMEOUT, command.output())
+        .await
+        .map_err(|_| AppError::Validation("Rendering the PDF timed out.".to_string()))?
+        .map_err(|e| AppError::Internal(format!("Failed to run {}: {}", renderer, e)))?;
+
+    let preview = if result.status.success() {
+        match tokio::fs::read(&page).await {
+            Ok(png) => image_preview(&png),
+            Err(e) => Err(AppError::Internal(format!(
+                "Failed to read {:?}: {}",
+                page, e
+            ))),
+        }
+    } else {
+        Err(AppError::Validation(format!(
+            "Unreadable PDF: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        )))
+    };
+    let _ = tokio::fs::remove_file(&page).await;
+
+    preview
+}
+
+async fn generate(store: &dyn FileStore, sha256: &str, mime_type: &str) -> AppResult<Preview> {
+    let content = file_store::read_all(store, &file_store::blob_key(sha256)).await?;
+    if mime_type != PDF {
+        // Decoding is CPU bound.
+        return tokio::task::spawn_blocking(move || image_preview(&content))
+            .await
+            .unwrap_or_else(|e| Err(AppError::Internal(e.to_string())));
+    }
+
+    let dir = storage::temp_dir();
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to create {:?}: {}", dir, e)))?;
+    let source = dir.join(format!("{}.preview.pdf", sha256));
+    tokio::fs::write(&source, content)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to write {:?}: {}", source, e)))?;
+    let preview = render_pdf(&source).await;
+    let _ = tokio::fs::remove_file(&source).await;
+
+    preview
+}
+
+async fn store_preview(store: &dyn FileStore, sha256: &str, preview: &Preview) -> AppResult<()> {
+    let path = storage::temp_dir().join(format!("{}.preview.png", sha256));
+    tokio::fs::write(&path, &preview.png)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to write {:?}: {}", path, e)))?;
+    let result = store.put(&file_store::preview_key(sha256), &path).await;
+    let _ = tokio::fs::remove_file(&path).await;
+
+    result
+}
+
+/// Generates the previews of the next batch of content without one. Returns whether there
+/// may be more.
+async fn generate_pending(store: &dyn FileStore, max_size: u64) -> AppResult<bool> {
+    let pending = sql_operations::get_pending_previews(BATCH_SIZE).await?;
+
+    for blob in &pending {
+        let (status, size) = if !is_supported(&blob.mime_type) {
+            (PreviewStatus::Unsupported, None)
+        } else if blob.size > max_size {
+            (PreviewStatus::TooLarge, None)
+        } else {
+            match generate(store, &blob.sha256, &blob.mime_type).await {
+                Ok(preview) => {
+                    store_preview(store, &blob.sha256, &preview).await?;
+                    (
+                        PreviewStatus::Generated,
+                        Some((preview.width, preview.height)),
+                    )
+                }
+                Err(e) => {
+                    warn!("Failed to generate the preview of {}: {}", blob.sha256, e);
+                    (PreviewStatus::Failed, None)
+                }
+            }
+        };
+
+        sql_operations::save_preview(blob.sha256.clone(), status, size).await?;
+    }
+
+    if !pending.is_empty() {
+        info!("Ran preview generation on {} files", pending.len());
+    }
+
+    Ok(pending.len() == BATCH_SIZE as usize)
+}
+
+/// Wakes the preview generation job when new content has been stored.
+#[derive(Clone, Default)]
+pub struct PreviewGenerator {
+    wake: Arc<Notify>,
+}
+
+impl PreviewGenerator {
+    pub fn notify(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Generates PNG previews of stored PDFs (their first page) and images. Runs whenever
+    /// it is notified, and every `PREVIEW_INTERVAL_SECS` (five minutes by default) for
+    /// anything it missed. Files over `PREVIEW_MAX_BYTES` (50 MiB by default) are skipped.
+    /// Previews of released content are removed by the sweeper.
+    pub async fn run(self, store: Arc<dyn FileStore>) {
+        let interval = env_secs("PREVIEW_INTERVAL_SECS", 300);
+        let max_size = std::env::var("PREVIEW_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(50 * 1024 * 1024);
+
+        loop {
+            match generate_pending(store.as_ref(), max_size).await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => error!("Preview generation failed: {}", e),
+            }
+
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbImage::from_pixel(width, height, Rgb([200, 30, 30]));
+        let mut png = Cursor::new(Vec::new());
+        image.write_to(&mut png, ImageFormat::Png).unwrap();
+        png.into_inner()
+    }
+
+    #[test]
+    fn test_image_preview() {
+        let preview = image_preview(&png(1280, 640)).unwrap();
+        assert_eq!((preview.width, preview.height), (320, 160));
+
+        let decoded = image::load_from_memory(&preview.png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (320, 160));
+
+        let preview = image_preview(&png(100, 50)).unwrap();
+        assert_eq!((preview.width, preview.height), (100, 50));
+    }
+
+    #[test]
+    fn test_image_preview_invalid() {
+        let result = image_preview(b"not an image");
+        assert!(matches!(result, Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn test_preview_status() {
+        for status in PreviewStatus::ALL {
+            assert_eq!(PreviewStatus::parse(status.as_str()).unwrap(), status);
+        }
+        assert!(is_supported("application/pdf"));
+        assert!(is_supported("image/jpeg"));
+        assert!(!is_supported("text/plain"));
+    }
+}
@@ -0,0 +1,161 @@
+use data_access::{AppError, AppResult};
+
+const MIB: u64 = 1024 * 1024;
+
+fn env_bytes(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Upload limits from `MAX_UPLOAD_SIZE_BYTES`, `USER_STORAGE_QUOTA_BYTES` and
+/// `CHANNEL_STORAGE_QUOTA_BYTES`. A user or channel with a `storage_quota_bytes` of its own
+/// uses that instead of the default quota.
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaLimits {
+    pub max_file_size: u64,
+    pub user_quota: u64,
+    pub channel_quota: u64,
+}
+
+impl QuotaLimits {
+    pub fn from_env() -> Self {
+        QuotaLimits {
+            max_file_size: env_bytes("MAX_UPLOAD_SIZE_BYTES", 100 * MIB),
+            user_quota: env_bytes("USER_STORAGE_QUOTA_BYTES", 1024 * MIB),
+            channel_quota: env_bytes("CHANNEL_STORAGE_QUOTA_BYTES", 5 * 1024 * MIB),
+        }
+    }
+
+    /// These limits with the quotas of a user and a channel that have their own.
+    pub fn with_overrides(self, user_quota: Option<u64>, channel_quota: Option<u64>) -> Self {
+        QuotaLimits {
+            user_quota: user_quota.unwrap_or(self.user_quota),
+            channel_quota: channel_quota.unwrap_or(self.channel_quota),
+            ..self
+        }
+    }
+
+    /// Fails if storing `size` more bytes would break one of the limits, given what the
+    /// uploader and the channel already use.
+    pub fn check(&self, usage: &StorageUsage, size: u64) -> AppResult<()> {
+        if size > self.max_file_size {
+            return Err(AppError::QuotaExceeded(format!(
+                "Files can be at most {} bytes.",
+                self.max_file_size
+            )));
+        }
+        if usage.user_bytes.saturating_add(size) > self.user_quota {
+            return Err(AppError::QuotaExceeded(
+                "Your storage quota has been reached.".to_string(),
+            ));
+        }
+        self.check_channel(usage.channel_bytes, size)
+    }
+
+    /// Fails if `size` more bytes don't fit in a channel using `channel_bytes`.
+    pub fn check_channel(&self, channel_bytes: u64, size: u64) -> AppResult<()> {
+        if channel_bytes.saturating_add(size) > self.channel_quota {
+            return Err(AppError::QuotaExceeded(
+                "The channel's storage quota has been reached.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Bytes already stored by a user and in a channel.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StorageUsage {
+    pub user_bytes: u64,
+    pub channel_bytes: u64,
+}
+
+impl StorageUsage {
+    /// The usage once a file of `size` bytes in the channel is replaced. It only counts
+    /// against the user if `own_file`.
+    pub fn without_file(self, size: u64, own_file: bool) -> Self {
+        StorageUsage {
+            user_bytes: match own_file {
+                true => self.user_bytes.saturating_sub(size),
+                false => self.user_bytes,
+            },
+            channel_bytes: self.channel_bytes.saturating_sub(size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: QuotaLimits = QuotaLimits {
+        max_file_size: 100,
+        user_quota: 1000,
+        channel_quota: 500,
+    };
+
+    #[test]
+    fn test_check_max_file_size() {
+        assert!(LIMITS.check(&StorageUsage::default(), 100).is_ok());
+
+        let result = LIMITS.check(&StorageUsage::default(), 101);
+        assert!(matches!(result, Err(AppError::QuotaExceeded(_))));
+    }
+
+    #[test]
+    fn test_check_quotas() {
+        let usage = StorageUsage {
+            user_bytes: 950,
+            channel_bytes: 0,
+        };
+        assert!(LIMITS.check(&usage, 50).is_ok());
+        assert!(matches!(
+            LIMITS.check(&usage, 51),
+            Err(AppError::QuotaExceeded(_))
+        ));
+
+        let usage = StorageUsage {
+            user_bytes: 0,
+            channel_bytes: 450,
+        };
+        assert!(matches!(
+            LIMITS.check(&usage, 51),
+            Err(AppError::QuotaExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_with_overrides() {
+        let limits = LIMITS.with_overrides(Some(2000), None);
+        assert_eq!(limits.user_quota, 2000);
+        assert_eq!(limits.channel_quota, 500);
+        assert_eq!(limits.max_file_size, 100);
+
+        let limits = LIMITS.with_overrides(None, Some(0));
+        assert_eq!(limits.user_quota, 1000);
+        assert!(matches!(
+            limits.check(&StorageUsage::default(), 1),
+            Err(AppError::QuotaExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_usage_without_file() {
+        let usage = StorageUsage {
+            user_bytes: 950,
+            channel_bytes: 450,
+        };
+        assert!(LIMITS.check(&usage.without_file(100, true), 100).is_ok());
+
+        let usage = usage.without_file(100, false);
+        assert_eq!(usage.user_bytes, 950);
+        assert_eq!(usage.channel_bytes, 350);
+        assert!(matches!(
+            LIMITS.check(&usage, 100),
+            Err(AppError::QuotaExceeded(_))
+        ));
+    }
+}
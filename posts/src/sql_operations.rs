@@ -1,150 +1,1339 @@
-use crate::post::Post;
+use crate::access::ChannelAccess;
+use crate::listing::{self, PageCursor, PostOrder, PostQuery};
+use crate::post::{FeedPost, NewFile, NewPost, PendingContent, Post, StoredFile, UploadSession};
+use crate::previews::{PreviewStatus, StoredPreview};
+use crate::quota::{QuotaLimits, StorageUsage};
+use crate::text_extraction::TextStatus;
 use actix_web::cookie::time::Date;
-use mysql::{params, prelude::Queryable, Row};
+use data_access::{AppError, AppResult};
+use mysql::{params, prelude::Queryable, IsolationLevel, Params, Row, TxOpts, Value};
+use std::future::Future;
 
-pub async fn get_posts_by_channel_id(channel_id: u32) -> Result<Vec<Post>, mysql::Error> {
-    let mut conn = data_access::get_connection_safe()?;
-    let query = "SELECT * FROM posts WHERE channel_id = :channel_id";
+/// Posts with their file name and the average rating of their comments, to be followed by
+/// conditions on `posts`. Listings select from it as the derived table `listed`.
+const LISTED_POSTS: &str = "SELECT posts.post_id, posts.channel_id, posts.file_id,
+        posts.title, posts.description, posts.publish_date, files.name AS file_name,
+        COALESCE((SELECT AVG(rating) FROM comments
+            WHERE comments.post_id = posts.post_id), 0) AS average_rating
+    FROM posts INNER JOIN files ON files.file_id = posts.file_id";
 
-    let mut posts: Vec<Post> = Vec::new();
+fn post(mut row: Row) -> Post {
+    let pdate: Date = row.take("publish_date").unwrap();
+    let pdate_str = pdate.to_string();
 
-    conn.exec_map(
-        query,
+    Post {
+        post_id: row.take("post_id").unwrap(),
+        channel_id: row.take("channel_id").unwrap(),
+        file_id: row.take("file_id").unwrap(),
+        title: row.take("title").unwrap(),
+        description: row.take("description").unwrap(),
+        publish_date: pdate_str,
+        average_rating: row.take("average_rating").unwrap(),
+    }
+}
+
+/// One page of the channel's posts, and the cursor to continue after it if there are more.
+pub async fn get_posts_by_channel_id(
+    query: PostQuery,
+) -> AppResult<(Vec<Post>, Option<PageCursor>)> {
+    let mut conditions = vec!["listed.channel_id = :channel_id".to_string()];
+    let mut values: Vec<(String, Value)> = vec![
+        ("channel_id".to_string(), query.channel_id.into()),
+        ("limit".to_string(), (query.page_size + 1).into()),
+    ];
+
+    if let Some(from) = query.published_from {
+        conditions.push("listed.publish_date >= :published_from".to_string());
+        values.push(("published_from".to_string(), from.to_string().into()));
+    }
+    if let Some(to) = query.published_to {
+        conditions.push("listed.publish_date <= :published_to".to_string());
+        values.push(("published_to".to_string(), to.to_string().into()));
+    }
+    if let Some(file_type) = &query.file_type {
+        conditions
+            .push("LOWER(SUBSTRING_INDEX(listed.file_name, '.', -1)) = :file_type".to_string());
+        values.push(("file_type".to_string(), file_type.to_lowercase().into()));
+    }
+    if let Some(title) = &query.title_contains {
+        conditions.push("listed.title LIKE :title ESCAPE '\\\\'".to_string());
+        values.push((
+            "title".to_string(),
+            format!("%{}%", listing::escape_like(title)).into(),
+        ));
+    }
+
+    let column = query.order.sort_column();
+    let (comparison, direction) = match query.order.descending() {
+        true => ("<", "DESC"),
+        false => (">", "ASC"),
+    };
+    if let Some(after) = &query.after {
+        conditions.push(format!(
+            "(listed.{}, listed.post_id) {} (:after_key, :after_id)",
+            column, comparison
+        ));
+        values.push(("after_key".to_string(), after.sort_key.clone().into()));
+        values.push(("after_id".to_string(), after.post_id.into()));
+    }
+
+    let sql = format!(
+        "SELECT * FROM ({}) AS listed
+        WHERE {} ORDER BY listed.{} {}, listed.post_id {} LIMIT :limit",
+        LISTED_POSTS,
+        conditions.join(" AND "),
+        column,
+        direction,
+        direction
+    );
+
+    let mut posts =
+        data_access::with_connection(move |conn| conn.exec_map(sql, Params::from(values), post))
+            .await?;
+
+    let next = if posts.len() > query.page_size as usize {
+        posts.truncate(query.page_size as usize);
+        posts.last().map(|last| query.order.cursor(last))
+    } else {
+        None
+    };
+
+    Ok((posts, next))
+}
+
+/// One page of the newest posts of the channels the user subscribes to, and the cursor to
+/// continue after it if there are more.
+pub async fn get_feed(
+    user_id: u32,
+    page_size: u32,
+    after: Option<PageCursor>,
+) -> AppResult<(Vec<FeedPost>, Option<PageCursor>)> {
+    let mut values: Vec<(String, Value)> = vec![
+        ("user_id".to_string(), user_id.into()),
+        ("limit".to_string(), (page_size + 1).into()),
+    ];
+    let mut condition = "";
+    if let Some(after) = &after {
+        condition = "AND (posts.publish_date, posts.post_id) < (:after_key, :after_id)";
+        values.push(("after_key".to_string(), after.sort_key.clone().into()));
+        values.push(("after_id".to_string(), after.post_id.into()));
+    }
+
+    // Filtering inside the derived table keeps it to the subscribed channels' posts.
+    let sql = format!(
+        "SELECT feed.*, channels.name AS channel_name FROM (
+            {} WHERE posts.channel_id IN
+                (SELECT channel_id FROM subscriptions WHERE user_id = :user_id)
+            {}
+        ) AS feed
+        INNER JOIN channels ON channels.channel_id = feed.channel_id
+        ORDER BY feed.publish_date DESC, feed.post_id DESC LIMIT :limit",
+        LISTED_POSTS, condition
+    );
+
+    let mut posts = data_access::with_connection(move |conn| {
+        conn.exec_map(sql, Params::from(values), |mut row: Row| {
+            let channel_name = row.take("channel_name").unwrap();
+            FeedPost {
+                post: post(row),
+                channel_name,
+            }
+        })
+    })
+    .await?;
+
+    let next = if posts.len() > page_size as usize {
+        posts.truncate(page_size as usize);
+        posts
+            .last()
+            .map(|last| PostOrder::Newest.cursor(&last.post))
+    } else {
+        None
+    };
+
+    Ok((posts, next))
+}
+
+pub async fn get_post(post_id: u32) -> AppResult<Post> {
+    let query = format!("{} WHERE posts.post_id = :post_id", LISTED_POSTS);
+
+    let result: Option<Row> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "post_id" => post_id })
+    })
+    .await?;
+
+    result
+        .map(post)
+        .ok_or_else(|| AppError::NotFound("Post not found.".to_string()))
+}
+
+const STORAGE_USAGE_QUERY: &str = "SELECT
+        CAST(COALESCE(SUM(CASE WHEN files.uploader_id = :user_id THEN files.size END), 0) AS UNSIGNED),
+        CAST(COALESCE(SUM(CASE WHEN posts.channel_id = :channel_id THEN files.size END), 0) AS UNSIGNED)
+    FROM files INNER JOIN posts ON posts.file_id = files.file_id
+    WHERE files.uploader_id = :user_id OR posts.channel_id = :channel_id";
+
+fn query_storage_usage<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    channel_id: u32,
+) -> Result<StorageUsage, mysql::Error> {
+    let usage: Option<(u64, u64)> = conn.exec_first(
+        STORAGE_USAGE_QUERY,
+        params! { "user_id" => user_id, "channel_id" => channel_id },
+    )?;
+
+    Ok(usage
+        .map(|(user_bytes, channel_bytes)| StorageUsage {
+            user_bytes,
+            channel_bytes,
+        })
+        .unwrap_or_default())
+}
+
+/// Bytes stored by the user across all channels, and by anyone in the channel.
+pub async fn get_storage_usage(user_id: u32, channel_id: u32) -> AppResult<StorageUsage> {
+    let usage =
+        data_access::with_connection(move |conn| query_storage_usage(conn, user_id, channel_id))
+            .await?;
+
+    Ok(usage)
+}
+
+/// `limits` with the quotas set on the user's and the channel's rows. The rows stay locked
+/// until the end of the transaction if `for_update`.
+fn query_quota_limits<Q: Queryable>(
+    conn: &mut Q,
+    user_id: u32,
+    channel_id: u32,
+    limits: QuotaLimits,
+    for_update: bool,
+) -> Result<QuotaLimits, mysql::Error> {
+    let lock = if for_update { " FOR UPDATE" } else { "" };
+    let user_quota: Option<Option<u64>> = conn.exec_first(
+        format!(
+            "SELECT storage_quota_bytes FROM users WHERE user_id = :user_id{}",
+            lock
+        ),
+        params! { "user_id" => user_id },
+    )?;
+    let channel_quota: Option<Option<u64>> = conn.exec_first(
+        format!(
+            "SELECT storage_quota_bytes FROM channels WHERE channel_id = :channel_id{}",
+            lock
+        ),
         params! { "channel_id" => channel_id },
-        |mut row: Row| {
-            let pdate: Date = row.take("publish_date").unwrap();
-            let pdate_str = pdate.to_string();
-
-            let post = Post {
-                post_id: row.take("post_id").unwrap(),
-                channel_id: row.take("channel_id").unwrap(),
-                file_id: row.take("file_id").unwrap(),
-                title: row.take("title").unwrap(),
-                description: row.take("description").unwrap(),
-                publish_date: pdate_str,
-            };
-            posts.push(post);
-        },
     )?;
 
-    Ok(posts)
+    Ok(limits.with_overrides(user_quota.flatten(), channel_quota.flatten()))
+}
+
+/// The upload limits of the user in the channel: the defaults from the environment, with
+/// the quotas the user or the channel have of their own.
+pub async fn get_quota_limits(user_id: u32, channel_id: u32) -> AppResult<QuotaLimits> {
+    let limits = data_access::with_connection(move |conn| {
+        query_quota_limits(conn, user_id, channel_id, QuotaLimits::from_env(), false)
+    })
+    .await?;
+
+    Ok(limits)
 }
 
-pub async fn create_post(
-    uuid: String,
+/// Locks the uploader and the channel rows, so concurrent uploads can't overshoot the quotas
+/// together, and checks that `size` more bytes fit. The quotas set on the rows override
+/// `limits`, and the bytes of a file being `replaced` don't count.
+fn check_quota_locked<Q: Queryable>(
+    conn: &mut Q,
+    uploader_id: u32,
     channel_id: u32,
-    file_name: String,
-    title: String,
-    description: String,
-) -> Result<bool, mysql::Error> {
-    let mut conn = data_access::get_connection_safe()?;
+    size: u64,
+    replaced: Option<&str>,
+    limits: QuotaLimits,
+) -> AppResult<()> {
+    let limits = query_quota_limits(conn, uploader_id, channel_id, limits, true)?;
+    let mut usage = query_storage_usage(conn, uploader_id, channel_id)?;
 
-    let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
-    let first_query = "INSERT INTO files (file_id, name) VALUES (:file_id, :file_name)";
-    transaction.exec_iter(
-        first_query,
-        params! {
-            "file_id" => &uuid,
-            "file_name" => file_name,
-        },
+    if let Some(file_id) = replaced {
+        let old: Option<(u64, Option<u32>)> = conn.exec_first(
+            "SELECT size, uploader_id FROM files WHERE file_id = :file_id",
+            params! { "file_id" => file_id },
+        )?;
+        if let Some((old_size, old_uploader_id)) = old {
+            usage = usage.without_file(old_size, old_uploader_id == Some(uploader_id));
+        }
+    }
+
+    limits.check(&usage, size)
+}
+
+/// Takes a reference on the file's content and inserts the file row. Returns whether the
+/// content still has to be written to the store: it is new, or whoever added it first hasn't
+/// stored it yet. Writing the same content twice is harmless.
+fn insert_file<Q: Queryable>(conn: &mut Q, file: &NewFile) -> Result<bool, mysql::Error> {
+    conn.exec_drop(
+        "INSERT INTO blobs (sha256, size, ref_count) VALUES (:sha256, :size, 1)
+        ON DUPLICATE KEY UPDATE ref_count = ref_count + 1",
+        params! { "sha256" => &file.sha256, "size" => file.size },
+    )?;
+    let stored: Option<bool> = conn.exec_first(
+        "SELECT stored FROM blobs WHERE sha256 = :sha256",
+        params! { "sha256" => &file.sha256 },
     )?;
 
-    let second_query = "INSERT INTO posts (channel_id, file_id, title, description, publish_date)
-        VALUES (:channel_id, :file_id, :title, :description, NOW())";
-    transaction.exec_iter(
-        second_query,
+    let query = "INSERT INTO files (file_id, name, size, sha256, mime_type, uploader_id)
+        VALUES (:file_id, :file_name, :size, :sha256, :mime_type, :uploader_id)";
+    conn.exec_drop(
+        query,
         params! {
-            "channel_id" => channel_id,
-            "file_id" => uuid,
-            "title" => title,
-            "description" => description,
+            "file_id" => &file.file_id,
+            "file_name" => &file.file_name,
+            "size" => file.size,
+            "sha256" => &file.sha256,
+            "mime_type" => &file.mime_type,
+            "uploader_id" => file.uploader_id,
         },
     )?;
 
-    let affected_rows = transaction.affected_rows();
-    let mut result = false;
-    if affected_rows == 1 {
-        transaction.commit()?;
-        result = true;
-    } else {
-        transaction.rollback()?;
-    }
+    Ok(stored != Some(true))
+}
 
-    Ok(result)
+/// Deletes the file row, and with it the post still using it, and releases its reference
+/// on the content. Returns `None` if there is no such file, otherwise the store key of the
+/// content if nobody refers to it any more.
+fn remove_file<Q: Queryable>(
+    conn: &mut Q,
+    file_id: &str,
+) -> Result<Option<Option<String>>, mysql::Error> {
+    let query = format!(
+        "{} WHERE files.file_id = :file_id FOR UPDATE",
+        STORED_FILE_QUERY
+    );
+    let file: Option<Row> = conn.exec_first(query, params! { "file_id" => file_id })?;
+    let Some(file) = file.map(stored_file) else {
+        return Ok(None);
+    };
+
+    conn.exec_drop(
+        "DELETE FROM files WHERE file_id = :file_id",
+        params! { "file_id" => file_id },
+    )?;
+
+    let Some(sha256) = &file.sha256 else {
+        return Ok(Some(Some(file.key())));
+    };
+    conn.exec_drop(
+        "UPDATE blobs SET ref_count = ref_count - 1 WHERE sha256 = :sha256",
+        params! { "sha256" => sha256 },
+    )?;
+    let released = conn
+        .exec_iter(
+            "DELETE FROM blobs WHERE sha256 = :sha256 AND ref_count = 0",
+            params! { "sha256" => sha256 },
+        )?
+        .affected_rows()
+        == 1;
+
+    Ok(Some(released.then(|| file.key())))
 }
 
-pub async fn _delete_post_by_file_uuid(uuid: String) -> Result<bool, mysql::Error> {
-    let mut conn = data_access::get_connection_safe()?;
+fn insert_post<Q: Queryable>(conn: &mut Q, post: NewPost, limits: QuotaLimits) -> AppResult<bool> {
+    let file = &post.file;
+    check_quota_locked(
+        conn,
+        file.uploader_id,
+        post.channel_id,
+        file.size,
+        None,
+        limits,
+    )?;
+    let new_content = insert_file(conn, file)?;
 
-    let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
-    let query = "DELETE FROM files WHERE file_id = :file_id";
-    transaction.exec_iter(
+    let query = "INSERT INTO posts (channel_id, file_id, title, description, publish_date)
+        VALUES (:channel_id, :file_id, :title, :description, NOW())";
+    conn.exec_drop(
         query,
         params! {
-            "file_id" => &uuid,
+            "channel_id" => post.channel_id,
+            "file_id" => &post.file.file_id,
+            "title" => post.title,
+            "description" => post.description,
         },
     )?;
-    let affected_rows = transaction.affected_rows();
 
-    let mut result = false;
-    if affected_rows == 1 {
+    Ok(new_content)
+}
+
+/// Inserts the file and its post and takes a reference on the file's content. The quotas
+/// are checked again under row locks on the uploader and the channel.
+///
+/// Returns whether the content still has to be written to the store, see [`insert_file`].
+pub async fn create_post(post: NewPost, limits: QuotaLimits) -> AppResult<bool> {
+    data_access::with_connection(move |conn| -> AppResult<bool> {
+        let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
+        let new_content = insert_post(&mut transaction, post, limits)?;
         transaction.commit()?;
-        result = true;
-    } else {
-        transaction.rollback()?;
-    }
 
-    Ok(result)
+        Ok(new_content)
+    })
+    .await
+}
+
+/// Creates the post of an upload session, see [`create_post`]. The session is kept until
+/// its content is stored and then removed with [`delete_upload_session`]; it remembers the
+/// file it was committed as, so that a commit is only retried once that file has been rolled
+/// back. Returns the file the session is committed as and whether its content still has to
+/// be written to the store.
+pub async fn commit_upload_session(
+    session_id: String,
+    post: NewPost,
+    limits: QuotaLimits,
+) -> AppResult<(String, bool)> {
+    data_access::with_connection(move |conn| -> AppResult<(String, bool)> {
+        let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
+
+        let session: Option<Option<String>> = transaction.exec_first(
+            "SELECT file_id FROM upload_sessions
+            WHERE session_id = :session_id AND expires_at > NOW() FOR UPDATE",
+            params! { "session_id" => &session_id },
+        )?;
+        let committed =
+            session.ok_or_else(|| AppError::NotFound("Upload session not found.".to_string()))?;
+        if let Some(file_id) = committed {
+            let stored: Option<bool> = transaction.exec_first(
+                "SELECT blobs.stored FROM files
+                JOIN blobs ON blobs.sha256 = files.sha256
+                WHERE files.file_id = :file_id",
+                params! { "file_id" => &file_id },
+            )?;
+            match stored {
+                // Stored before the session could be removed.
+                Some(true) => return Ok((file_id, false)),
+                Some(false) => {
+                    return Err(AppError::Conflict(
+                        "The upload session is already being committed.".to_string(),
+                    ))
+                }
+                None => {}
+            }
+        }
+
+        let file_id = post.file.file_id.clone();
+        let new_content = insert_post(&mut transaction, post, limits)?;
+        transaction.exec_drop(
+            "UPDATE upload_sessions SET file_id = :file_id WHERE session_id = :session_id",
+            params! { "file_id" => &file_id, "session_id" => session_id },
+        )?;
+        transaction.commit()?;
+
+        Ok((file_id, new_content))
+    })
+    .await
+}
+
+/// Adds a file that is about to replace the file `replaced` in the channel, see
+/// [`replace_post_file`]. Returns whether the content still has to be written to the store.
+pub async fn add_replacement_file(
+    file: NewFile,
+    channel_id: u32,
+    replaced: String,
+    limits: QuotaLimits,
+) -> AppResult<bool> {
+    data_access::with_connection(move |conn| -> AppResult<bool> {
+        let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
+
+        check_quota_locked(
+            &mut transaction,
+            file.uploader_id,
+            channel_id,
+            file.size,
+            Some(&replaced),
+            limits,
+        )?;
+        let new_content = insert_file(&mut transaction, &file)?;
+
+        transaction.commit()?;
+
+        Ok(new_content)
+    })
+    .await
+}
+
+/// Marks the content as written to the store, from when on it is shared with later uploads
+/// of the same content and processed by the background jobs.
+pub async fn mark_content_stored(sha256: String) -> AppResult<()> {
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(
+            "UPDATE blobs SET stored = true WHERE sha256 = :sha256",
+            params! { "sha256" => sha256 },
+        )
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Runs `delete` unless a file refers to the content again. The content's row, or the gap
+/// where it would be, stays locked meanwhile, so an upload of the same content waits and
+/// then stores it anew. Returns whether `delete` ran.
+pub async fn delete_unreferenced_content<F>(sha256: String, delete: F) -> AppResult<bool>
+where
+    F: Future<Output = AppResult<()>> + Send + 'static,
+{
+    let runtime = tokio::runtime::Handle::current();
+    data_access::with_connection(move |conn| -> AppResult<bool> {
+        // Only repeatable read locks the gap when there is no row.
+        let options = TxOpts::default().set_isolation_level(Some(IsolationLevel::RepeatableRead));
+        let mut transaction = conn.start_transaction(options)?;
+
+        let referenced: Option<String> = transaction.exec_first(
+            "SELECT sha256 FROM blobs WHERE sha256 = :sha256 FOR UPDATE",
+            params! { "sha256" => sha256 },
+        )?;
+        if referenced.is_some() {
+            return Ok(false);
+        }
+
+        // This runs on the blocking pool, where waiting on the store is fine.
+        runtime.block_on(delete)?;
+        transaction.commit()?;
+
+        Ok(true)
+    })
+    .await
+}
+
+/// Points the post at `new_file_id` and removes its previous file. Returns the store key of
+/// the previous content if nobody refers to it any more.
+pub async fn replace_post_file(
+    post_id: u32,
+    old_file_id: String,
+    new_file_id: String,
+) -> AppResult<Option<String>> {
+    data_access::with_connection(move |conn| -> AppResult<Option<String>> {
+        let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
+
+        let updated = transaction
+            .exec_iter(
+                "UPDATE posts SET file_id = :new_file_id
+                WHERE post_id = :post_id AND file_id = :old_file_id",
+                params! {
+                    "post_id" => post_id,
+                    "old_file_id" => &old_file_id,
+                    "new_file_id" => new_file_id,
+                },
+            )?
+            .affected_rows();
+        if updated == 0 {
+            return Err(AppError::Conflict(
+                "The post was changed or deleted in the meantime.".to_string(),
+            ));
+        }
+
+        let released = remove_file(&mut transaction, &old_file_id)?.flatten();
+        transaction.commit()?;
+
+        Ok(released)
+    })
+    .await
 }
 
-pub async fn get_file_name(uuid: String) -> Result<String, mysql::Error> {
-    let mut conn = data_access::get_connection();
+/// Deletes the post together with its file. Returns the store key of the content if nobody
+/// refers to it any more.
+pub async fn delete_post(post_id: u32) -> AppResult<Option<String>> {
+    data_access::with_connection(move |conn| -> AppResult<Option<String>> {
+        let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
+
+        let file_id: Option<String> = transaction.exec_first(
+            "SELECT file_id FROM posts WHERE post_id = :post_id FOR UPDATE",
+            params! { "post_id" => post_id },
+        )?;
+        let Some(file_id) = file_id else {
+            return Err(AppError::NotFound("Post not found.".to_string()));
+        };
+
+        let released = remove_file(&mut transaction, &file_id)?.flatten();
+        transaction.commit()?;
+
+        Ok(released)
+    })
+    .await
+}
+
+/// Deletes the file and its post, if any, and releases its reference on the content.
+/// Content nobody refers to any more is removed from the store by the sweeper.
+pub async fn delete_post_by_file_uuid(uuid: String) -> AppResult<bool> {
+    data_access::with_connection(move |conn| {
+        let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
+        let removed = remove_file(&mut transaction, &uuid)?;
+        transaction.commit()?;
+
+        Ok(removed.is_some())
+    })
+    .await
+}
+
+/// Updates the post's title and description, and moves it to `channel_id` if given. A
+/// moved post counts against the new channel's quota.
+pub async fn update_post(
+    post_id: u32,
+    title: String,
+    description: String,
+    channel_id: Option<u32>,
+    limits: QuotaLimits,
+) -> AppResult<()> {
+    data_access::with_connection(move |conn| -> AppResult<()> {
+        let mut transaction = conn.start_transaction(mysql::TxOpts::default())?;
+
+        let file: Option<(u64, bool)> = transaction.exec_first(
+            "SELECT files.size, files.sha256 IS NULL FROM posts
+            INNER JOIN files ON files.file_id = posts.file_id
+            WHERE posts.post_id = :post_id FOR UPDATE",
+            params! { "post_id" => post_id },
+        )?;
+        let Some((size, legacy_file)) = file else {
+            return Err(AppError::NotFound("Post not found.".to_string()));
+        };
+
+        if let Some(channel_id) = channel_id {
+            // Files stored before content hashing are kept under their channel.
+            if legacy_file {
+                return Err(AppError::Validation(
+                    "This post's file can't be moved to another channel.".to_string(),
+                ));
+            }
+
+            let limits = query_quota_limits(&mut transaction, 0, channel_id, limits, true)?;
+            let usage = query_storage_usage(&mut transaction, 0, channel_id)?;
+            limits.check_channel(usage.channel_bytes, size)?;
+        }
+
+        transaction.exec_drop(
+            "UPDATE posts SET title = :title, description = :description,
+                channel_id = COALESCE(:channel_id, channel_id)
+            WHERE post_id = :post_id",
+            params! {
+                "post_id" => post_id,
+                "title" => title,
+                "description" => description,
+                "channel_id" => channel_id,
+            },
+        )?;
+
+        transaction.commit()?;
+        Ok(())
+    })
+    .await
+}
+
+pub async fn get_file_name(uuid: String) -> AppResult<String> {
     let query = "SELECT name FROM files WHERE file_id = :file_id";
 
-    let mut result: String = Default::default();
+    let result: Option<String> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "file_id" => uuid })
+    })
+    .await?;
+
+    result.ok_or_else(|| AppError::NotFound("File not found.".to_string()))
+}
 
-    conn.exec_map(query, params! { "file_id" => uuid }, |mut row: Row| {
-        result = row.take("name").unwrap()
-    })?;
+pub async fn get_channel_creator(channel_id: u32) -> AppResult<u32> {
+    let query = "SELECT creator_id FROM channels WHERE channel_id = :channel_id";
 
-    Ok(result)
+    let result: Option<u32> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "channel_id" => channel_id })
+    })
+    .await?;
+
+    result.ok_or_else(|| AppError::NotFound("Channel not found.".to_string()))
+}
+
+pub async fn get_channel_access(channel_id: u32, user_id: u32) -> AppResult<ChannelAccess> {
+    let query = "SELECT creator_id, visibility = 'members' AS members_only,
+        EXISTS(SELECT 1 FROM subscriptions
+            WHERE subscriptions.channel_id = channels.channel_id
+            AND subscriptions.user_id = :user_id) AS subscribed
+        FROM channels WHERE channel_id = :channel_id";
+
+    let result: Option<(u32, bool, bool)> = data_access::with_connection(move |conn| {
+        conn.exec_first(
+            query,
+            params! { "channel_id" => channel_id, "user_id" => user_id },
+        )
+    })
+    .await?;
+
+    result
+        .map(|(creator_id, members_only, subscribed)| ChannelAccess {
+            creator_id,
+            members_only,
+            subscribed,
+        })
+        .ok_or_else(|| AppError::NotFound("Channel not found.".to_string()))
+}
+
+pub async fn get_file_channel(uuid: String) -> AppResult<u32> {
+    let query = "SELECT channel_id FROM posts WHERE file_id = :file_id";
+
+    let result: Option<u32> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "file_id" => uuid })
+    })
+    .await?;
+
+    result.ok_or_else(|| AppError::NotFound("File not found.".to_string()))
+}
+
+// Files being swapped into a post by `ReplacePostFile` don't have a post yet.
+const STORED_FILE_QUERY: &str = "SELECT files.file_id,
+        COALESCE(posts.channel_id, 0) AS channel_id, files.name, files.size, files.sha256,
+        files.mime_type, files.uploader_id
+    FROM files LEFT JOIN posts ON posts.file_id = files.file_id";
+
+fn stored_file(mut row: Row) -> StoredFile {
+    StoredFile {
+        file_id: row.take("file_id").unwrap(),
+        channel_id: row.take("channel_id").unwrap(),
+        name: row.take("name").unwrap(),
+        size: row.take("size").unwrap(),
+        sha256: row.take("sha256").unwrap(),
+        mime_type: row.take("mime_type").unwrap(),
+        uploader_id: row.take("uploader_id").unwrap(),
+    }
+}
+
+pub async fn get_stored_file(uuid: String) -> AppResult<StoredFile> {
+    let query = format!("{} WHERE files.file_id = :file_id", STORED_FILE_QUERY);
+
+    let result: Option<Row> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "file_id" => uuid })
+    })
+    .await?;
+
+    result
+        .map(stored_file)
+        .ok_or_else(|| AppError::NotFound("File not found.".to_string()))
+}
+
+/// Every stored file with the channel its post belongs to, for reconciling with storage.
+pub async fn get_stored_files() -> AppResult<Vec<StoredFile>> {
+    let files =
+        data_access::with_connection(move |conn| conn.query_map(STORED_FILE_QUERY, stored_file))
+            .await?;
+
+    Ok(files)
+}
+
+/// Creates the session, which expires `ttl_secs` from now.
+pub async fn create_upload_session(session: UploadSession, ttl_secs: u64) -> AppResult<()> {
+    let query = "INSERT INTO upload_sessions
+        (session_id, user_id, channel_id, file_name, title, description, total_size,
+            created_at, expires_at)
+        VALUES (:session_id, :user_id, :channel_id, :file_name, :title, :description,
+            :total_size, NOW(), NOW() + INTERVAL :ttl_secs SECOND)";
+
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(
+            query,
+            params! {
+                "session_id" => session.session_id,
+                "user_id" => session.user_id,
+                "channel_id" => session.channel_id,
+                "file_name" => session.file_name,
+                "title" => session.title,
+                "description" => session.description,
+                "total_size" => session.total_size,
+                "ttl_secs" => ttl_secs,
+            },
+        )
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// The session, unless it doesn't exist or has expired.
+pub async fn get_upload_session(session_id: String) -> AppResult<UploadSession> {
+    let query = "SELECT session_id, user_id, channel_id, file_name, title, description,
+            total_size, CAST(expires_at AS CHAR) AS expires_at
+        FROM upload_sessions WHERE session_id = :session_id AND expires_at > NOW()";
+
+    let result: Option<Row> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "session_id" => session_id })
+    })
+    .await?;
+
+    result
+        .map(|mut row| UploadSession {
+            session_id: row.take("session_id").unwrap(),
+            user_id: row.take("user_id").unwrap(),
+            channel_id: row.take("channel_id").unwrap(),
+            file_name: row.take("file_name").unwrap(),
+            title: row.take("title").unwrap(),
+            description: row
+                .take::<Option<String>, _>("description")
+                .unwrap()
+                .unwrap_or_default(),
+            total_size: row.take("total_size").unwrap(),
+            expires_at: row.take("expires_at").unwrap(),
+        })
+        .ok_or_else(|| AppError::NotFound("Upload session not found.".to_string()))
+}
+
+pub async fn delete_upload_session(session_id: String) -> AppResult<()> {
+    let query = "DELETE FROM upload_sessions WHERE session_id = :session_id";
+
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(query, params! { "session_id" => session_id })
+    })
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_expired_upload_sessions() -> AppResult<()> {
+    let query = "DELETE FROM upload_sessions WHERE expires_at <= NOW()";
+
+    data_access::with_connection(move |conn| conn.query_drop(query)).await?;
+
+    Ok(())
+}
+
+pub async fn get_upload_session_ids() -> AppResult<Vec<String>> {
+    let query = "SELECT session_id FROM upload_sessions";
+
+    let ids = data_access::with_connection(move |conn| conn.query(query)).await?;
+
+    Ok(ids)
+}
+
+/// Stored content without a row in `table` yet, which holds the results of a background
+/// job keyed by `sha256`.
+async fn get_pending_content(table: &'static str, limit: u32) -> AppResult<Vec<PendingContent>> {
+    let query = format!(
+        "SELECT blobs.sha256, blobs.size, COALESCE(MIN(files.mime_type), '')
+        FROM blobs INNER JOIN files ON files.sha256 = blobs.sha256
+        LEFT JOIN {table} ON {table}.sha256 = blobs.sha256
+        WHERE blobs.stored AND {table}.sha256 IS NULL
+        GROUP BY blobs.sha256, blobs.size
+        LIMIT :limit",
+        table = table
+    );
+
+    let pending = data_access::with_connection(move |conn| {
+        conn.exec_map(
+            query,
+            params! { "limit" => limit },
+            |(sha256, size, mime_type)| PendingContent {
+                sha256,
+                size,
+                mime_type,
+            },
+        )
+    })
+    .await?;
+
+    Ok(pending)
+}
+
+pub async fn get_pending_texts(limit: u32) -> AppResult<Vec<PendingContent>> {
+    get_pending_content("blob_texts", limit).await
+}
+
+/// Records the extracted text of the content, unless it was released in the meantime.
+pub async fn save_text(sha256: String, status: TextStatus, text: Option<String>) -> AppResult<()> {
+    let query = "INSERT INTO blob_texts (sha256, status, content, extracted_at)
+        SELECT sha256, :status, :content, NOW() FROM blobs WHERE sha256 = :sha256
+        ON DUPLICATE KEY UPDATE status = :status, content = :content, extracted_at = NOW()";
+
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(
+            query,
+            params! {
+                "sha256" => sha256,
+                "status" => status.as_str(),
+                "content" => text,
+            },
+        )
+    })
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_pending_previews(limit: u32) -> AppResult<Vec<PendingContent>> {
+    get_pending_content("blob_previews", limit).await
+}
+
+/// Records the outcome of the content's preview generation, unless the content was
+/// released in the meantime. `size` is the width and height of a generated preview.
+pub async fn save_preview(
+    sha256: String,
+    status: PreviewStatus,
+    size: Option<(u32, u32)>,
+) -> AppResult<()> {
+    let query = "INSERT INTO blob_previews (sha256, status, width, height, generated_at)
+        SELECT sha256, :status, :width, :height, NOW() FROM blobs WHERE sha256 = :sha256
+        ON DUPLICATE KEY UPDATE status = :status, width = :width, height = :height,
+            generated_at = NOW()";
+    let (width, height) = size.unwrap_or_default();
+
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(
+            query,
+            params! {
+                "sha256" => sha256,
+                "status" => status.as_str(),
+                "width" => width,
+                "height" => height,
+            },
+        )
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// The preview of the content, `None` while it hasn't been generated yet.
+pub async fn get_preview(sha256: String) -> AppResult<Option<StoredPreview>> {
+    let query = "SELECT status, width, height FROM blob_previews WHERE sha256 = :sha256";
+
+    let row: Option<(String, u32, u32)> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "sha256" => sha256 })
+    })
+    .await?;
+
+    row.map(|(status, width, height)| {
+        Ok(StoredPreview {
+            status: PreviewStatus::parse(&status)?,
+            width,
+            height,
+        })
+    })
+    .transpose()
+}
+
+/// Hashes of the content with a generated preview in the store.
+pub async fn get_preview_hashes() -> AppResult<Vec<String>> {
+    let query = "SELECT sha256 FROM blob_previews WHERE status = 'generated'";
+
+    let hashes = data_access::with_connection(move |conn| conn.query(query)).await?;
+
+    Ok(hashes)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::file_store;
+    use sha2::{Digest, Sha256};
     use uuid::Uuid;
 
+    fn new_file(uuid: &str) -> NewFile {
+        NewFile {
+            file_id: uuid.to_string(),
+            uploader_id: 2,
+            file_name: "test.pdf".to_string(),
+            size: 1024,
+            sha256: hex::encode(Sha256::digest(uuid)),
+            mime_type: "application/pdf".to_string(),
+        }
+    }
+
+    fn new_post(uuid: &str) -> NewPost {
+        NewPost {
+            channel_id: 1,
+            title: "Test Post".to_string(),
+            description: "This is a test post".to_string(),
+            file: new_file(uuid),
+        }
+    }
+
+    async fn post_id_of(uuid: &str) -> u32 {
+        let (posts, _) = get_posts_by_channel_id(PostQuery::new(1)).await.unwrap();
+        posts
+            .iter()
+            .find(|post| post.file_id == uuid)
+            .unwrap()
+            .post_id
+    }
+
     #[tokio::test]
     async fn test_get_posts_by_channel() {
         // pre
-        let channel_id = 1;
-        let file_name = "test.pdf".to_string();
-        let title = "Test Post".to_string();
-        let description = "This is a test post".to_string();
         let uuid = Uuid::new_v4().to_string();
-        let _ = create_post(uuid.clone(), channel_id, file_name, title, description).await;
+        let _ = create_post(new_post(&uuid), QuotaLimits::from_env()).await;
 
-        let result = get_posts_by_channel_id(1).await;
-        let posts = result.unwrap();
+        let result = get_posts_by_channel_id(PostQuery::new(1)).await;
+        let (posts, _) = result.unwrap();
         assert!(!posts.is_empty());
 
         // post
-        let _ = _delete_post_by_file_uuid(uuid).await;
+        let _ = delete_post_by_file_uuid(uuid).await;
     }
 
     #[tokio::test]
     async fn test_create_post() {
-        let channel_id = 1;
-        let file_name = "test.pdf".to_string();
-        let title = "Test Post".to_string();
-        let description = "This is a test post".to_string();
         let uuid = Uuid::new_v4().to_string();
-        let result = create_post(uuid.clone(), channel_id, file_name, title, description).await;
+        let result = create_post(new_post(&uuid), QuotaLimits::from_env()).await;
 
         assert!(result.unwrap());
+        let file = get_stored_file(uuid.clone()).await.unwrap();
+        assert_eq!(file.sha256, Some(new_post(&uuid).file.sha256));
+
+        // post
+        let _ = delete_post_by_file_uuid(uuid).await;
+    }
+
+    #[tokio::test]
+    async fn test_create_post_shares_content() {
+        let first = Uuid::new_v4().to_string();
+        let second = Uuid::new_v4().to_string();
+        let third = Uuid::new_v4().to_string();
+        let mut duplicate = new_post(&second);
+        duplicate.file.sha256 = new_post(&first).file.sha256;
+
+        let limits = QuotaLimits::from_env();
+        assert!(create_post(new_post(&first), limits).await.unwrap());
+        // Until the first upload has stored the content, the next one stores it too.
+        let mut pending = new_post(&third);
+        pending.file.sha256 = new_post(&first).file.sha256;
+        assert!(create_post(pending, limits).await.unwrap());
+        mark_content_stored(new_post(&first).file.sha256)
+            .await
+            .unwrap();
+        assert!(!create_post(duplicate, limits).await.unwrap());
+
+        let first_key = get_stored_file(first.clone()).await.unwrap().key();
+        let second_key = get_stored_file(second.clone()).await.unwrap().key();
+        assert_eq!(first_key, second_key);
+
+        // post
+        let _ = delete_post_by_file_uuid(first).await;
+        let _ = delete_post_by_file_uuid(second).await;
+        let _ = delete_post_by_file_uuid(third).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_unreferenced_content() {
+        let uuid = Uuid::new_v4().to_string();
+        let sha256 = new_file(&uuid).sha256;
+        create_post(new_post(&uuid), QuotaLimits::from_env())
+            .await
+            .unwrap();
+
+        let deleted = delete_unreferenced_content(sha256.clone(), async { Ok(()) }).await;
+        assert!(!deleted.unwrap());
+
+        delete_post_by_file_uuid(uuid).await.unwrap();
+        let deleted = delete_unreferenced_content(sha256, async { Ok(()) }).await;
+        assert!(deleted.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_get_channel_creator() {
+        let result = get_channel_creator(1).await;
+        assert_eq!(result.unwrap(), 2);
+
+        let result = get_channel_creator(0).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_get_channel_access() {
+        let access = get_channel_access(1, 1).await.unwrap();
+        assert_eq!(access.creator_id, 2);
+        assert!(access.subscribed);
+
+        let access = get_channel_access(1, 3).await.unwrap();
+        assert!(!access.subscribed);
+    }
+
+    #[tokio::test]
+    async fn test_get_file_channel_invalid() {
+        let result = get_file_channel("not-a-file".to_string()).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_create_post_over_quota() {
+        let uuid = Uuid::new_v4().to_string();
+        let limits = QuotaLimits {
+            max_file_size: 2048,
+            user_quota: 512,
+            channel_quota: 2048,
+        };
+        let result = create_post(new_post(&uuid), limits).await;
+
+        assert!(matches!(result, Err(AppError::QuotaExceeded(_))));
+    }
+
+    async fn set_user_quota(user_id: u32, quota: Option<u64>) {
+        data_access::with_connection(move |conn| {
+            conn.exec_drop(
+                "UPDATE users SET storage_quota_bytes = :quota WHERE user_id = :user_id",
+                params! { "quota" => quota, "user_id" => user_id },
+            )
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_quota_override() {
+        let uuid = Uuid::new_v4().to_string();
+        let mut post = new_post(&uuid);
+        post.file.uploader_id = 3;
+
+        set_user_quota(3, Some(512)).await;
+        let limits = get_quota_limits(3, 1).await.unwrap();
+        let result = create_post(post, QuotaLimits::from_env()).await;
+        set_user_quota(3, None).await;
+
+        assert_eq!(limits.user_quota, 512);
+        assert!(matches!(result, Err(AppError::QuotaExceeded(_))));
+        let limits = get_quota_limits(3, 1).await.unwrap();
+        assert_eq!(limits.user_quota, QuotaLimits::from_env().user_quota);
+    }
+
+    #[tokio::test]
+    async fn test_get_storage_usage() {
+        let uuid = Uuid::new_v4().to_string();
+        let before = get_storage_usage(2, 1).await.unwrap();
+        create_post(new_post(&uuid), QuotaLimits::from_env())
+            .await
+            .unwrap();
+
+        let after = get_storage_usage(2, 1).await.unwrap();
+        assert_eq!(after.user_bytes, before.user_bytes + 1024);
+        assert_eq!(after.channel_bytes, before.channel_bytes + 1024);
+
+        // post
+        let _ = delete_post_by_file_uuid(uuid).await;
+    }
+
+    #[tokio::test]
+    async fn test_upload_session() {
+        let session_id = Uuid::new_v4().to_string();
+        let session = UploadSession {
+            session_id: session_id.clone(),
+            user_id: 2,
+            channel_id: 1,
+            file_name: "lecture.pdf".to_string(),
+            title: "Lecture".to_string(),
+            description: String::new(),
+            total_size: 4096,
+            expires_at: String::new(),
+        };
+        create_upload_session(session, 3600).await.unwrap();
+
+        let session = get_upload_session(session_id.clone()).await.unwrap();
+        assert_eq!(session.total_size, 4096);
+        assert!(get_upload_session_ids()
+            .await
+            .unwrap()
+            .contains(&session_id));
+
+        let uuid = Uuid::new_v4().to_string();
+        let limits = QuotaLimits::from_env();
+        let (file_id, _) = commit_upload_session(session_id.clone(), new_post(&uuid), limits)
+            .await
+            .unwrap();
+        assert_eq!(file_id, uuid);
+        assert!(get_upload_session(session_id.clone()).await.is_ok());
+
+        // Committed, but the content isn't stored yet.
+        let again = Uuid::new_v4().to_string();
+        let result = commit_upload_session(session_id.clone(), new_post(&again), limits).await;
+        assert!(matches!(result, Err(AppError::Conflict(_))));
+        assert!(get_stored_file(again.clone()).await.is_err());
+
+        // Rolled back after storing the content failed.
+        delete_post_by_file_uuid(uuid).await.unwrap();
+        let (file_id, _) = commit_upload_session(session_id.clone(), new_post(&again), limits)
+            .await
+            .unwrap();
+        assert_eq!(file_id, again);
+
+        delete_upload_session(session_id.clone()).await.unwrap();
+        let result = get_upload_session(session_id.clone()).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+        let result =
+            commit_upload_session(session_id, new_post(&Uuid::new_v4().to_string()), limits).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+
+        // post
+        let _ = delete_post_by_file_uuid(again).await;
+    }
+
+    #[tokio::test]
+    async fn test_update_post() {
+        let uuid = Uuid::new_v4().to_string();
+        create_post(new_post(&uuid), QuotaLimits::from_env())
+            .await
+            .unwrap();
+        let post_id = post_id_of(&uuid).await;
+
+        let limits = QuotaLimits::from_env();
+        update_post(post_id, "Renamed".to_string(), String::new(), None, limits)
+            .await
+            .unwrap();
+
+        let post = get_post(post_id).await.unwrap();
+        assert_eq!(post.title, "Renamed");
+        assert_eq!(post.description, "");
+
+        let result = update_post(0, "Renamed".to_string(), String::new(), None, limits).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+
+        // post
+        let _ = delete_post_by_file_uuid(uuid).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_post() {
+        let uuid = Uuid::new_v4().to_string();
+        create_post(new_post(&uuid), QuotaLimits::from_env())
+            .await
+            .unwrap();
+        let post_id = post_id_of(&uuid).await;
+
+        let released = delete_post(post_id).await.unwrap();
+        assert_eq!(
+            released,
+            Some(file_store::blob_key(&new_file(&uuid).sha256))
+        );
+
+        assert!(matches!(
+            get_post(post_id).await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            delete_post(post_id).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_replace_post_file() {
+        let old = Uuid::new_v4().to_string();
+        let new = Uuid::new_v4().to_string();
+        let limits = QuotaLimits::from_env();
+        create_post(new_post(&old), limits).await.unwrap();
+        let post_id = post_id_of(&old).await;
+
+        assert!(add_replacement_file(new_file(&new), 1, old.clone(), limits)
+            .await
+            .unwrap());
+        let released = replace_post_file(post_id, old.clone(), new.clone())
+            .await
+            .unwrap();
+        assert_eq!(released, Some(file_store::blob_key(&new_file(&old).sha256)));
+
+        assert_eq!(get_post(post_id).await.unwrap().file_id, new);
+        assert!(matches!(
+            get_stored_file(old).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        // post
+        let _ = delete_post_by_file_uuid(new).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_posts_by_channel_pages() {
+        let first = Uuid::new_v4().to_string();
+        let second = Uuid::new_v4().to_string();
+        let limits = QuotaLimits::from_env();
+        create_post(new_post(&first), limits).await.unwrap();
+        create_post(new_post(&second), limits).await.unwrap();
+
+        let mut query = PostQuery::new(1);
+        query.page_size = 1;
+        let (page, next) = get_posts_by_channel_id(query.clone()).await.unwrap();
+        assert_eq!(page[0].file_id, second);
+
+        query.after = next;
+        let (page, _) = get_posts_by_channel_id(query.clone()).await.unwrap();
+        assert_eq!(page[0].file_id, first);
+
+        query.after = None;
+        query.title_contains = Some("no post has this title".to_string());
+        let (page, next) = get_posts_by_channel_id(query).await.unwrap();
+        assert!(page.is_empty());
+        assert!(next.is_none());
+
+        // post
+        let _ = delete_post_by_file_uuid(first).await;
+        let _ = delete_post_by_file_uuid(second).await;
+    }
+
+    #[tokio::test]
+    async fn test_save_text() {
+        let uuid = Uuid::new_v4().to_string();
+        let sha256 = new_file(&uuid).sha256;
+        create_post(new_post(&uuid), QuotaLimits::from_env())
+            .await
+            .unwrap();
+
+        let is_pending =
+            |pending: Vec<PendingContent>| pending.iter().any(|blob| blob.sha256 == sha256);
+        // Content is only processed once it has been stored.
+        assert!(!is_pending(get_pending_texts(u32::MAX).await.unwrap()));
+        mark_content_stored(sha256.clone()).await.unwrap();
+        assert!(is_pending(get_pending_texts(u32::MAX).await.unwrap()));
+
+        let text = Some("Software design".to_string());
+        save_text(sha256.clone(), TextStatus::Extracted, text)
+            .await
+            .unwrap();
+        assert!(!is_pending(get_pending_texts(u32::MAX).await.unwrap()));
+
+        // post
+        let _ = delete_post_by_file_uuid(uuid).await;
+    }
+
+    #[tokio::test]
+    async fn test_save_preview() {
+        let uuid = Uuid::new_v4().to_string();
+        let sha256 = new_file(&uuid).sha256;
+        create_post(new_post(&uuid), QuotaLimits::from_env())
+            .await
+            .unwrap();
+        assert!(get_preview(sha256.clone()).await.unwrap().is_none());
+
+        save_preview(sha256.clone(), PreviewStatus::Generated, Some((320, 240)))
+            .await
+            .unwrap();
+        let preview = get_preview(sha256.clone()).await.unwrap().unwrap();
+        assert_eq!(preview.status, PreviewStatus::Generated);
+        assert_eq!((preview.width, preview.height), (320, 240));
+        assert!(get_preview_hashes().await.unwrap().contains(&sha256));
+
+        // post
+        let _ = delete_post_by_file_uuid(uuid).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_feed() {
+        // User 1 subscribes to channel 1, where the test posts are created.
+        let first = Uuid::new_v4().to_string();
+        let second = Uuid::new_v4().to_string();
+        let limits = QuotaLimits::from_env();
+        create_post(new_post(&first), limits).await.unwrap();
+        create_post(new_post(&second), limits).await.unwrap();
+
+        let (page, next) = get_feed(1, 1, None).await.unwrap();
+        assert_eq!(page[0].post.file_id, second);
+        assert!(!page[0].channel_name.is_empty());
+
+        let (page, _) = get_feed(1, 1, next).await.unwrap();
+        assert_eq!(page[0].post.file_id, first);
+
+        let (page, next) = get_feed(100, 10, None).await.unwrap();
+        assert!(page.is_empty());
+        assert!(next.is_none());
 
         // post
-        let _ = _delete_post_by_file_uuid(uuid).await;
+        let _ = delete_post_by_file_uuid(first).await;
+        let _ = delete_post_by_file_uuid(second).await;
     }
 }
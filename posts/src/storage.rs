@@ -0,0 +1,463 @@
+use crate::file_store::{self, FileStore};
+use crate::sql_operations;
+use crate::upload;
+use data_access::{AppError, AppResult};
+use log::{error, info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+pub fn env_secs(name: &str, default: u64) -> Duration {
+    Duration::from_secs(
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default),
+    )
+}
+
+/// Uploads in progress are staged in `UPLOAD_TMP_DIR` until their post is committed.
+pub fn temp_dir() -> PathBuf {
+    std::env::var("UPLOAD_TMP_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir().join("studyvault-uploads"))
+}
+
+/// Extension of the staged content of upload sessions, which outlives single requests.
+const SESSION_EXTENSION: &str = "session";
+
+fn io_error(action: &str, path: &Path, e: std::io::Error) -> AppError {
+    AppError::Internal(format!("Failed to {} {:?}: {}", action, path, e))
+}
+
+async fn create_temp_dir() -> AppResult<PathBuf> {
+    let dir = temp_dir();
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| io_error("create directory", &dir, e))?;
+    Ok(dir)
+}
+
+fn session_path(session_id: &str) -> PathBuf {
+    temp_dir().join(format!("{}.{}", session_id, SESSION_EXTENSION))
+}
+
+fn session_io_error(path: &Path, e: std::io::Error) -> AppError {
+    if e.kind() == ErrorKind::NotFound {
+        AppError::NotFound("Upload session not found.".to_string())
+    } else {
+        io_error("open", path, e)
+    }
+}
+
+/// Creates the empty staging file of a new upload session.
+pub async fn create_session_file(session_id: &str) -> AppResult<()> {
+    create_temp_dir().await?;
+    let path = session_path(session_id);
+    tokio::fs::File::create(&path)
+        .await
+        .map_err(|e| io_error("create file", &path, e))?;
+    Ok(())
+}
+
+/// How many bytes of an upload session have been received.
+pub async fn session_received_bytes(session_id: &str) -> AppResult<u64> {
+    let path = session_path(session_id);
+    let metadata = tokio::fs::metadata(&path)
+        .await
+        .map_err(|e| session_io_error(&path, e))?;
+    Ok(metadata.len())
+}
+
+/// Opens the staged content of an upload session for writing at `offset`, dropping anything
+/// received after it.
+pub async fn open_session_at(session_id: &str, offset: u64) -> AppResult<tokio::fs::File> {
+    let path = session_path(session_id);
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .await
+        .map_err(|e| session_io_error(&path, e))?;
+    file.set_len(offset)
+        .await
+        .map_err(|e| io_error("truncate", &path, e))?;
+    file.seek(std::io::SeekFrom::Start(offset))
+        .await
+        .map_err(|e| io_error("seek in", &path, e))?;
+    Ok(file)
+}
+
+pub async fn remove_session_file(session_id: &str) {
+    let path = session_path(session_id);
+    match tokio::fs::remove_file(&path).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            warn!("Failed to remove upload session {:?}: {}", path, e)
+        }
+        _ => {}
+    }
+}
+
+/// A file being uploaded. It is staged in [`temp_dir`] and only handed to the
+/// [`FileStore`] by [`PendingFile::persist`]. The staged copy is removed by `persist` or
+/// [`PendingFile::discard`]; dropping it removes it too, blocking, for requests that are
+/// cancelled halfway. The size and SHA-256 of the content are tracked as it is written.
+pub struct PendingFile {
+    path: PathBuf,
+    file: Option<tokio::fs::File>,
+    hasher: Sha256,
+    size: u64,
+    head: Vec<u8>,
+    remove_on_drop: bool,
+}
+
+impl PendingFile {
+    pub async fn create(file_id: &str) -> AppResult<Self> {
+        let path = create_temp_dir().await?.join(format!("{}.part", file_id));
+        let file = tokio::fs::File::create(&path)
+            .await
+            .map_err(|e| io_error("create file", &path, e))?;
+
+        Ok(PendingFile {
+            path,
+            file: Some(file),
+            hasher: Sha256::new(),
+            size: 0,
+            head: Vec::new(),
+            remove_on_drop: true,
+        })
+    }
+
+    /// The complete content of an upload session. Unlike uploads staged by [`create`], it
+    /// is only removed once persisted, so a commit that fails before the session is deleted
+    /// can be retried. The sweeper removes it otherwise.
+    ///
+    /// [`create`]: PendingFile::create
+    pub async fn from_session(session_id: &str) -> AppResult<Self> {
+        let path = session_path(session_id);
+        let mut file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|e| session_io_error(&path, e))?;
+
+        let mut pending = PendingFile {
+            path,
+            file: None,
+            hasher: Sha256::new(),
+            size: 0,
+            head: Vec::new(),
+            remove_on_drop: false,
+        };
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let bytes_read = file
+                .read(&mut buffer)
+                .await
+                .map_err(|e| io_error("read", &pending.path, e))?;
+            if bytes_read == 0 {
+                break;
+            }
+            pending.track(&buffer[..bytes_read]);
+        }
+
+        pending.file = Some(file);
+        Ok(pending)
+    }
+
+    fn track(&mut self, content: &[u8]) {
+        self.hasher.update(content);
+        self.size += content.len() as u64;
+        if self.head.len() < upload::MIME_SNIFF_LENGTH {
+            let take = content
+                .len()
+                .min(upload::MIME_SNIFF_LENGTH - self.head.len());
+            self.head.extend_from_slice(&content[..take]);
+        }
+    }
+
+    pub async fn write(&mut self, content: &[u8]) -> AppResult<()> {
+        let file = self.file.as_mut().expect("pending file already persisted");
+        file.write_all(content)
+            .await
+            .map_err(|e| io_error("write to", &self.path, e))?;
+
+        self.track(content);
+        Ok(())
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Hex encoded SHA-256 of everything written so far.
+    pub fn sha256(&self) -> String {
+        hex::encode(self.hasher.clone().finalize())
+    }
+
+    /// The start of the content, for [`upload::mime_type`].
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+
+    /// Flushes the upload to disk and stores it under `key`. The staged copy is removed
+    /// afterwards, even if storing it failed; the content of a session only once stored.
+    pub async fn persist(mut self, store: &dyn FileStore, key: &str) -> AppResult<()> {
+        let result = self.put(store, key).await;
+        if result.is_ok() {
+            self.remove_on_drop = true;
+        }
+        self.discard().await;
+
+        result
+    }
+
+    async fn put(&mut self, store: &dyn FileStore, key: &str) -> AppResult<()> {
+        let file = self.file.take().expect("pending file already persisted");
+        file.sync_all()
+            .await
+            .map_err(|e| io_error("flush", &self.path, e))?;
+        drop(file);
+
+        store.put(key, &self.path).await
+    }
+
+    /// Removes the staged copy of an upload that won't be stored. The content of a session
+    /// is kept so that its commit can be retried.
+    pub async fn discard(mut self) {
+        if !self.remove_on_drop {
+            return;
+        }
+        self.remove_on_drop = false;
+        drop(self.file.take());
+        log_remove_error(&self.path, tokio::fs::remove_file(&self.path).await);
+    }
+}
+
+fn log_remove_error(path: &Path, result: std::io::Result<()>) {
+    match result {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            warn!("Failed to remove staged upload {:?}: {}", path, e)
+        }
+        _ => {}
+    }
+}
+
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        if self.remove_on_drop {
+            log_remove_error(&self.path, std::fs::remove_file(&self.path));
+        }
+    }
+}
+
+fn is_older_than(modified: SystemTime, age: Duration) -> bool {
+    SystemTime::now()
+        .duration_since(modified)
+        .is_ok_and(|elapsed| elapsed > age)
+}
+
+/// Removes staged uploads abandoned for longer than `grace`, except the content of
+/// `live_sessions`.
+async fn sweep_temp_dir(grace: Duration, live_sessions: &HashSet<String>) -> AppResult<usize> {
+    let dir = temp_dir();
+    let mut removed = 0;
+
+    let mut entries = match tokio::fs::read_dir(&dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(removed),
+        Err(e) => return Err(io_error("read", &dir, e)),
+    };
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| io_error("read", &dir, e))?
+    {
+        let path = entry.path();
+        let is_session = path
+            .extension()
+            .is_some_and(|extension| extension == SESSION_EXTENSION);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        if is_session && live_sessions.contains(stem.as_ref()) {
+            continue;
+        }
+
+        let Ok(modified) = entry.metadata().await.and_then(|m| m.modified()) else {
+            continue;
+        };
+        if is_older_than(modified, grace) && tokio::fs::remove_file(&path).await.is_ok() {
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Removes an object nobody refers to any more. Shared content is only removed while no file
+/// refers to it, see [`sql_operations::delete_unreferenced_content`].
+pub async fn delete_released(store: &Arc<dyn FileStore>, key: &str) -> AppResult<()> {
+    let Some(sha256) = file_store::blob_sha256(key) else {
+        return store.delete(key).await;
+    };
+
+    let (store, key) = (store.clone(), key.to_string());
+    sql_operations::delete_unreferenced_content(sha256.to_string(), async move {
+        store.delete(&key).await
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Reconciles the `files` table with the store. Removes expired upload sessions, stale
+/// staged uploads and stored objects no file or preview refers to once they are older than
+/// `grace`, and logs rows whose object is missing. This is also how shared content and its
+/// preview are released after its last file is deleted.
+pub async fn sweep(store: &Arc<dyn FileStore>, grace: Duration) -> AppResult<()> {
+    sql_operations::delete_expired_upload_sessions().await?;
+    let live_sessions: HashSet<String> = sql_operations::get_upload_session_ids()
+        .await?
+        .into_iter()
+        .collect();
+    let mut removed = sweep_temp_dir(grace, &live_sessions).await?;
+
+    let stored = sql_operations::get_stored_files().await?;
+    let mut known: HashSet<String> = stored.iter().map(|file| file.key()).collect();
+    let previews = sql_operations::get_preview_hashes().await?;
+    known.extend(
+        previews
+            .iter()
+            .map(|sha256| file_store::preview_key(sha256)),
+    );
+    let mut found = HashSet::new();
+
+    for object in store.list().await? {
+        if known.contains(&object.key) {
+            found.insert(object.key);
+        } else if is_older_than(object.modified, grace) {
+            match delete_released(store, &object.key).await {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("Failed to remove orphaned file {}: {}", object.key, e),
+            }
+        }
+    }
+
+    for file in stored.iter().filter(|file| !found.contains(&file.key())) {
+        warn!(
+            "File {} of channel {} is in the database but missing from storage",
+            file.file_id, file.channel_id
+        );
+    }
+    if removed > 0 {
+        info!("Removed {} orphaned files from storage", removed);
+    }
+
+    Ok(())
+}
+
+/// Runs [`sweep`] at startup and then every `FILE_SWEEP_INTERVAL_SECS` (an hour by default).
+pub async fn run_sweeper(store: Arc<dyn FileStore>) {
+    let grace = env_secs("FILE_SWEEP_GRACE_SECS", 3600);
+    let mut interval = tokio::time::interval(env_secs("FILE_SWEEP_INTERVAL_SECS", 3600));
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = sweep(&store, grace).await {
+            error!("File sweep failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_store::LocalFileStore;
+
+    fn use_test_temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join("posts-storage-test");
+        std::env::set_var("UPLOAD_TMP_DIR", &dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_pending_file_removed_on_drop() {
+        use_test_temp_dir();
+        let mut file = PendingFile::create("dropped").await.unwrap();
+        file.write(b"partial").await.unwrap();
+        let path = file.path.clone();
+
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_pending_file_discard() {
+        use_test_temp_dir();
+        let mut file = PendingFile::create("discarded").await.unwrap();
+        file.write(b"partial").await.unwrap();
+        let path = file.path.clone();
+
+        file.discard().await;
+        assert!(!path.exists());
+
+        create_session_file("kept").await.unwrap();
+        PendingFile::from_session("kept")
+            .await
+            .unwrap()
+            .discard()
+            .await;
+        assert_eq!(session_received_bytes("kept").await.unwrap(), 0);
+        remove_session_file("kept").await;
+    }
+
+    #[tokio::test]
+    async fn test_session_file_resume() {
+        use_test_temp_dir();
+        create_session_file("resumed").await.unwrap();
+
+        let mut file = open_session_at("resumed", 0).await.unwrap();
+        file.write_all(b"contents").await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(session_received_bytes("resumed").await.unwrap(), 8);
+
+        let mut file = open_session_at("resumed", 7).await.unwrap();
+        file.write_all(b"!").await.unwrap();
+        file.flush().await.unwrap();
+
+        let pending = PendingFile::from_session("resumed").await.unwrap();
+        assert_eq!(pending.size(), 8);
+        assert_eq!(pending.head(), b"content!");
+
+        drop(pending);
+        assert_eq!(session_received_bytes("resumed").await.unwrap(), 8);
+
+        remove_session_file("resumed").await;
+        assert!(matches!(
+            session_received_bytes("resumed").await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pending_file_persist() {
+        let dir = use_test_temp_dir();
+        let store = LocalFileStore::new(dir.join("store"));
+        let mut file = PendingFile::create("persisted").await.unwrap();
+        file.write(b"content").await.unwrap();
+        let temp_path = file.path.clone();
+
+        assert_eq!(file.size(), 7);
+        assert_eq!(
+            file.sha256(),
+            "ed7002b439e9ac845f22357d822bac1444730fbdb6016d3ec9432297b9ec9f73"
+        );
+
+        file.persist(&store, "1/persisted.txt").await.unwrap();
+
+        assert!(!temp_path.exists());
+        let destination = dir.join("store").join("1").join("persisted.txt");
+        assert_eq!(std::fs::read(&destination).unwrap(), b"content");
+        std::fs::remove_file(destination).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+use crate::file_store::{self, FileStore};
+use crate::sql_operations;
+use crate::storage::env_secs;
+use data_access::{AppError, AppResult};
+use log::{error, info, warn};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+const PDF: &str = "application/pdf";
+const DOCX: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+
+/// How much extracted text is kept per file, in bytes. The rest isn't searchable.
+const MAX_TEXT_LENGTH: usize = 1 << 20;
+/// Limit on the uncompressed body of a DOCX, against zip bombs.
+const MAX_DOCX_XML_LENGTH: u64 = 64 << 20;
+const BATCH_SIZE: u32 = 20;
+
+/// Outcome of the extraction of some content's text, as stored in `blob_texts.status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextStatus {
+    Extracted,
+    Unsupported,
+    TooLarge,
+    Failed,
+}
+
+impl TextStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TextStatus::Extracted => "extracted",
+            TextStatus::Unsupported => "unsupported",
+            TextStatus::TooLarge => "too_large",
+            TextStatus::Failed => "failed",
+        }
+    }
+}
+
+pub fn is_supported(mime_type: &str) -> bool {
+    mime_type.starts_with("text/") || mime_type == PDF || mime_type == DOCX
+}
+
+/// Extracts the text of a PDF, DOCX or plain text file, with whitespace collapsed and cut
+/// to [`MAX_TEXT_LENGTH`].
+pub fn extract_text(mime_type: &str, content: &[u8]) -> AppResult<String> {
+    let text = match mime_type {
+        PDF => pdf_extract::extract_text_from_mem(content)
+            .map_err(|e| AppError::Validation(format!("Unreadable PDF: {}", e)))?,
+        DOCX => docx_text(content)?,
+        _ => String::from_utf8_lossy(content).into_owned(),
+    };
+
+    Ok(normalize(&text))
+}
+
+/// The text of the runs of a Word document's body, one line per paragraph.
+fn docx_text(content: &[u8]) -> AppResult<String> {
+    let invalid =
+        |e: &dyn std::fmt::Display| AppError::Validation(format!("Unreadable DOCX: {}", e));
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(content)).map_err(|e| invalid(&e))?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .map_err(|e| invalid(&e))?
+        .take(MAX_DOCX_XML_LENGTH)
+        .read_to_string(&mut xml)
+        .map_err(|e| invalid(&e))?;
+
+    let mut reader = Reader::from_str(&xml);
+    let mut text = String::new();
+    let mut in_run_text = false;
+    loop {
+        match reader.read_event().map_err(|e| invalid(&e))? {
+            Event::Start(element) if element.local_name().as_ref() == b"t" => in_run_text = true,
+            Event::End(element) => match element.local_name().as_ref() {
+                b"t" => in_run_text = false,
+                b"p" => text.push('\n'),
+                _ => {}
+            },
+            Event::Empty(element) => match element.local_name().as_ref() {
+                b"tab" => text.push('\t'),
+                b"br" | b"cr" => text.push('\n'),
+                _ => {}
+            },
+            Event::Text(run) if in_run_text => {
+                text.push_str(&run.unescape().map_err(|e| invalid(&e))?)
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(text)
+}
+
+/// Collapses whitespace and control characters into single spaces and cuts the text to
+/// [`MAX_TEXT_LENGTH`] on a character boundary.
+fn normalize(text: &str) -> String {
+    let mut normalized = String::new();
+    let words = text
+        .split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|word| !word.is_empty());
+    for word in words {
+        if normalized.len() + word.len() + 1 > MAX_TEXT_LENGTH {
+            break;
+        }
+        if !normalized.is_empty() {
+            normalized.push(' ');
+        }
+        normalized.push_str(word);
+    }
+    normalized
+}
+
+/// Extracts the text of the next batch of content without one. Returns whether there may
+/// be more.
+async fn extract_pending(store: &dyn FileStore, max_size: u64) -> AppResult<bool> {
+    let pending = sql_operations::get_pending_texts(BATCH_SIZE).await?;
+
+    for blob in &pending {
+        let (status, text) = if !is_supported(&blob.mime_type) {
+            (TextStatus::Unsupported, None)
+        } else if blob.size > max_size {
+            (TextStatus::TooLarge, None)
+        } else {
+            let key = file_store::blob_key(&blob.sha256);
+            let result = match file_store::read_all(store, &key).await {
+                // Parsing is CPU bound, and a malformed document may make the parser panic.
+                Ok(content) => {
+                    let mime_type = blob.mime_type.clone();
+                    tokio::task::spawn_blocking(move || extract_text(&mime_type, &content))
+                        .await
+                        .unwrap_or_else(|e| Err(AppError::Internal(e.to_string())))
+                }
+                // Missing content is also reported by the sweeper.
+                Err(e @ AppError::NotFound(_)) => Err(e),
+                Err(e) => return Err(e),
+            };
+            match result {
+                Ok(text) => (TextStatus::Extracted, Some(text)),
+                Err(e) => {
+                    warn!("Failed to extract the text of {}: {}", blob.sha256, e);
+                    (TextStatus::Failed, None)
+                }
+            }
+        };
+
+        sql_operations::save_text(blob.sha256.clone(), status, text).await?;
+    }
+
+    if !pending.is_empty() {
+        info!("Ran text extraction on {} files", pending.len());
+    }
+
+    Ok(pending.len() == BATCH_SIZE as usize)
+}
+
+/// Wakes the text extraction job when new content has been stored.
+#[derive(Clone, Default)]
+pub struct TextExtractor {
+    wake: Arc<Notify>,
+}
+
+impl TextExtractor {
+    pub fn notify(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Extracts the text of stored PDF, DOCX and plain text files so that posts can be
+    /// searched by their content. Runs whenever it is notified, and every
+    /// `TEXT_EXTRACTION_INTERVAL_SECS` (five minutes by default) for anything it missed.
+    /// Files over `TEXT_EXTRACTION_MAX_BYTES` (50 MiB by default) are skipped.
+    pub async fn run(self, store: Arc<dyn FileStore>) {
+        let interval = env_secs("TEXT_EXTRACTION_INTERVAL_SECS", 300);
+        let max_size = std::env::var("TEXT_EXTRACTION_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(50 * 1024 * 1024);
+
+        loop {
+            match extract_pending(store.as_ref(), max_size).await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => error!("Text extraction failed: {}", e),
+            }
+
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn docx(document_xml: &str) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("word/document.xml", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(document_xml.as_bytes()).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_extract_docx() {
+        let content = docx(
+            r#"<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+                <w:body>
+                    <w:p><w:r><w:t>Software</w:t></w:r><w:r><w:t xml:space="preserve"> design</w:t></w:r></w:p>
+                    <w:p><w:r><w:t>Patterns &amp; practices</w:t><w:tab/><w:t>2024</w:t></w:r></w:p>
+                </w:body>
+            </w:document>"#,
+        );
+
+        let text = extract_text(DOCX, &content).unwrap();
+        assert_eq!(text, "Software design Patterns & practices 2024");
+    }
+
+    #[test]
+    fn test_extract_invalid_documents() {
+        let result = extract_text(DOCX, b"not a zip");
+        assert!(matches!(result, Err(AppError::Validation(_))));
+
+        let result = extract_text(PDF, b"%PDF-1.7\nnot really");
+        assert!(matches!(result, Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn test_extract_plain_text() {
+        let text = extract_text("text/markdown", b"# Notes\n\n  on\tsorting\0\r\n").unwrap();
+        assert_eq!(text, "# Notes on sorting");
+
+        let long = "word ".repeat(MAX_TEXT_LENGTH);
+        let text = extract_text("text/plain", long.as_bytes()).unwrap();
+        assert!(text.len() <= MAX_TEXT_LENGTH && text.ends_with("word"));
+    }
+
+    #[test]
+    fn test_is_supported() {
+        assert!(is_supported(PDF));
+        assert!(is_supported(DOCX));
+        assert!(is_supported("text/plain"));
+        assert!(!is_supported("image/png"));
+        assert!(!is_supported("application/msword"));
+    }
+}
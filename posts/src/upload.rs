@@ -0,0 +1,144 @@
+use crate::posts::UploadMetadata;
+use data_access::{AppError, AppResult};
+
+/// Column limits of `posts.title`, `posts.description` and `files.name`.
+const MAX_TITLE_LENGTH: usize = 32;
+const MAX_DESCRIPTION_LENGTH: usize = 256;
+const MAX_FILE_NAME_LENGTH: usize = 256;
+
+const DEFAULT_ALLOWED_EXTENSIONS: &str = "pdf,doc,docx,ppt,pptx,xls,xlsx,txt,md,png,jpg,jpeg,zip";
+
+/// Extensions accepted by `UploadPost`, from `ALLOWED_FILE_EXTENSIONS` (comma separated).
+fn allowed_extensions() -> Vec<String> {
+    std::env::var("ALLOWED_FILE_EXTENSIONS")
+        .unwrap_or_else(|_| DEFAULT_ALLOWED_EXTENSIONS.to_string())
+        .split(',')
+        .map(|extension| extension.trim().to_lowercase())
+        .filter(|extension| !extension.is_empty())
+        .collect()
+}
+
+/// Checks a post's title and description against the table limits.
+pub fn validate_post_text(title: &str, description: &str) -> AppResult<()> {
+    let title_length = title.trim().chars().count();
+    if title_length == 0 || title_length > MAX_TITLE_LENGTH {
+        return Err(AppError::Validation(format!(
+            "Title must be between 1 and {} characters.",
+            MAX_TITLE_LENGTH
+        )));
+    }
+
+    if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        return Err(AppError::Validation(format!(
+            "Description must be at most {} characters.",
+            MAX_DESCRIPTION_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+/// Checks the file name's length and extension, returning the extension.
+pub fn validate_file_name(file_name: &str) -> AppResult<String> {
+    if file_name.chars().count() > MAX_FILE_NAME_LENGTH {
+        return Err(AppError::Validation(format!(
+            "File name must be at most {} characters.",
+            MAX_FILE_NAME_LENGTH
+        )));
+    }
+
+    let extension = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => extension,
+        _ => {
+            return Err(AppError::Validation(
+                "File name must have an extension.".to_string(),
+            ))
+        }
+    };
+
+    if !allowed_extensions().contains(&extension.to_lowercase()) {
+        return Err(AppError::Validation(format!(
+            "Files of type .{} are not allowed.",
+            extension
+        )));
+    }
+
+    Ok(extension.to_string())
+}
+
+/// Checks the metadata against the table limits and the extension allowlist, returning
+/// the file extension to store the upload under.
+pub fn validate_metadata(metadata: &UploadMetadata) -> AppResult<String> {
+    validate_post_text(&metadata.title, &metadata.description)?;
+    validate_file_name(&metadata.filename)
+}
+
+/// How much of the start of an upload is kept for [`mime_type`].
+pub const MIME_SNIFF_LENGTH: usize = 8192;
+
+/// Detects the MIME type from the start of the content. Office documents are zip
+/// containers, so a plain zip (or unrecognised content) falls back to the extension.
+pub fn mime_type(head: &[u8], extension: &str) -> String {
+    match infer::get(head) {
+        Some(kind) if kind.mime_type() != "application/zip" => kind.mime_type().to_string(),
+        _ => mime_guess::from_ext(extension)
+            .first_or_octet_stream()
+            .to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(filename: &str, title: &str, description: &str) -> UploadMetadata {
+        UploadMetadata {
+            filename: filename.to_string(),
+            channel_id: 1,
+            title: title.to_string(),
+            description: description.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate_metadata() {
+        let result = validate_metadata(&metadata("notes.PDF", "Test Post", "A test post"));
+        assert_eq!(result.unwrap(), "PDF");
+    }
+
+    #[test]
+    fn test_validate_metadata_lengths() {
+        let long_title = "a".repeat(33);
+        let result = validate_metadata(&metadata("notes.pdf", &long_title, ""));
+        assert!(matches!(result, Err(AppError::Validation(_))));
+
+        let result = validate_metadata(&metadata("notes.pdf", " ", ""));
+        assert!(matches!(result, Err(AppError::Validation(_))));
+
+        let long_description = "á".repeat(257);
+        let result = validate_metadata(&metadata("notes.pdf", "Test", &long_description));
+        assert!(matches!(result, Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn test_validate_metadata_extension() {
+        let result = validate_metadata(&metadata("script.exe", "Test", ""));
+        assert!(matches!(result, Err(AppError::Validation(_))));
+
+        let result = validate_metadata(&metadata("README", "Test", ""));
+        assert!(matches!(result, Err(AppError::Validation(_))));
+
+        let result = validate_metadata(&metadata(".pdf", "Test", ""));
+        assert!(matches!(result, Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn test_mime_type() {
+        assert_eq!(mime_type(b"%PDF-1.7\n", "pdf"), "application/pdf");
+        assert_eq!(mime_type(b"plain notes", "md"), "text/markdown");
+        assert_eq!(
+            mime_type(b"PK\x03\x04", "docx"),
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use data_access::{AppError, AppResult};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long an upload session can be resumed, from `UPLOAD_SESSION_TTL_SECS` (a day by
+/// default).
+pub fn session_ttl() -> Duration {
+    Duration::from_secs(
+        std::env::var("UPLOAD_SESSION_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(24 * 3600),
+    )
+}
+
+/// Upload sessions with an `UploadPart` or commit in progress, so that two calls never
+/// write or read the same staged content at once.
+#[derive(Default)]
+pub struct ActiveSessions {
+    ids: Mutex<HashSet<String>>,
+}
+
+impl ActiveSessions {
+    pub fn claim(&self, session_id: &str) -> AppResult<ActiveSession<'_>> {
+        let mut ids = self.ids.lock().unwrap();
+        if !ids.insert(session_id.to_string()) {
+            return Err(AppError::Conflict(
+                "The upload session is already in use.".to_string(),
+            ));
+        }
+
+        Ok(ActiveSession {
+            sessions: self,
+            session_id: session_id.to_string(),
+        })
+    }
+}
+
+/// Releases the session when dropped.
+pub struct ActiveSession<'a> {
+    sessions: &'a ActiveSessions,
+    session_id: String,
+}
+
+impl Drop for ActiveSession<'_> {
+    fn drop(&mut self) {
+        self.sessions.ids.lock().unwrap().remove(&self.session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claim_session() {
+        let sessions = ActiveSessions::default();
+        let claimed = sessions.claim("a").unwrap();
+
+        assert!(matches!(sessions.claim("a"), Err(AppError::Conflict(_))));
+        assert!(sessions.claim("b").is_ok());
+
+        drop(claimed);
+        assert!(sessions.claim("a").is_ok());
+    }
+}
@@ -13,9 +13,10 @@ use utoipa_swagger_ui::SwaggerUi;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let url =
-        std::env::var("DATABASE_URL").expect("Couldn't get secret key from cargo environment");
-    let repo: MySQLSubscriptionsRepository = MySQLSubscriptionsRepository::new(&url);
+    let pool = data_access::get_pool()
+        .expect("Failed to create database pool")
+        .clone();
+    let repo: MySQLSubscriptionsRepository = MySQLSubscriptionsRepository::new(pool);
 
     HttpServer::new(move || {
         let cors = Cors::default()
@@ -35,6 +36,8 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .wrap(HttpAuthentication::bearer(validate_jwt))
             .app_data(web::Data::new(repo.clone()))
+            .app_data(data_access::error::json_config())
+            .app_data(data_access::error::path_config())
             .service(
                 SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", openapi.clone()),
             )
@@ -1,14 +1,15 @@
 use crate::model::Subscription;
 use async_trait::async_trait;
+use data_access::AppResult;
 
 #[async_trait]
 pub trait SubscriptionsRepository {
     async fn subscribe(
         &self,
         subscription: Subscription,
-    ) -> Result<bool, Box<dyn std::error::Error>>;
+    ) -> AppResult<()>;
     async fn unsubscribe(
         &self,
         subscription: Subscription,
-    ) -> Result<bool, Box<dyn std::error::Error>>;
+    ) -> AppResult<()>;
 }
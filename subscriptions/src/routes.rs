@@ -1,29 +1,29 @@
 use crate::model::Subscription;
 use crate::{repository::SubscriptionsRepository, sql_repo::MySQLSubscriptionsRepository};
-use actix_web::{delete, post, web, HttpResponse, Responder};
-use log::error;
+use actix_web::{delete, post, web, HttpResponse};
+use auth::AuthenticatedUser;
+use data_access::AppError;
 
 /// Subscribe an user to a channel given Subscription schema.
 #[utoipa::path(
     request_body = Subscription,
     responses(
         (status = 200, description = "Subscribes an user to a channel."),
+        (status = 400, description = "User or channel does not exist."),
+        (status = 403, description = "Users can only subscribe themselves."),
+        (status = 409, description = "User is already subscribed to the channel."),
         (status = 500, description = "Internal server error occurred."),
     )
 )]
 #[post("/subscription")]
 pub async fn create_subscription(
+    user: AuthenticatedUser,
     repo: web::Data<MySQLSubscriptionsRepository>,
     subscription: web::Json<Subscription>,
-) -> impl Responder {
-    match repo.subscribe(subscription.into_inner()).await {
-        Ok(true) => HttpResponse::Ok(),                   //200
-        Ok(false) => HttpResponse::InternalServerError(), //500
-        Err(e) => {
-            error!("Database exception ocurred: {}", e);
-            HttpResponse::InternalServerError()
-        } //500
-    }
+) -> Result<HttpResponse, AppError> {
+    user.ensure_owner(subscription.user_id)?;
+    repo.subscribe(subscription.into_inner()).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Unsubscribes an user from a channel given the Subscription schema.
@@ -31,20 +31,18 @@ pub async fn create_subscription(
     request_body = Subscription,
     responses(
         (status = 200, description = "Unsubscribes an user from a channel."),
+        (status = 403, description = "Users can only unsubscribe themselves."),
+        (status = 404, description = "Subscription not found."),
         (status = 500, description = "Internal server error occurred."),
     )
 )]
 #[delete("/unsubscribe")]
 pub async fn unsubscribe_from_channel(
+    user: AuthenticatedUser,
     repo: web::Data<MySQLSubscriptionsRepository>,
     subscription: web::Json<Subscription>,
-) -> impl Responder {
-    match repo.unsubscribe(subscription.into_inner()).await {
-        Ok(true) => HttpResponse::Ok(),                   //200
-        Ok(false) => HttpResponse::InternalServerError(), //500
-        Err(e) => {
-            error!("Database exception ocurred: {}", e);
-            HttpResponse::InternalServerError()
-        } //500
-    }
+) -> Result<HttpResponse, AppError> {
+    user.ensure_owner(subscription.user_id)?;
+    repo.unsubscribe(subscription.into_inner()).await?;
+    Ok(HttpResponse::Ok().finish())
 }
@@ -1,25 +1,24 @@
 use crate::sql_operations;
-use crate::user::{RegisterRequest, UserToUpdate, PasswordToUpdate};
-use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
-use log::error;
+use crate::user::{PasswordToUpdate, RegisterRequest, UserToUpdate};
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use auth::{session, AuthenticatedUser};
+use data_access::AppError;
 
-/// Register a new user.
+/// Register a new user. The account can't log in until its email is confirmed through
+/// the auth service's `/user/verification/request` and `/user/verify` endpoints.
 #[utoipa::path(
     request_body = RegisterRequest,
     responses(
-        (status = 200, description = "User registered successfully."),
+        (status = 200, description = "User registered successfully, pending email verification."),
+        (status = 400, description = "Email domain is not allowed."),
+        (status = 409, description = "Email is already registered."),
         (status = 500, description = "Internal server error occurred.")
     )
 )]
 #[post("/register")]
-pub async fn register_new_user(data: web::Json<RegisterRequest>) -> impl Responder {
-    match sql_operations::register_user(data.into_inner()).await {
-        true => HttpResponse::Ok().json("User registered successfully."),
-        false => {
-            error!("Failed to register user");
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+pub async fn register_new_user(data: web::Json<RegisterRequest>) -> Result<HttpResponse, AppError> {
+    sql_operations::register_user(data.into_inner()).await?;
+    Ok(HttpResponse::Ok().json("User registered successfully. Verify your email to log in."))
 }
 
 /// Retrieve all user emails.
@@ -30,9 +29,9 @@ pub async fn register_new_user(data: web::Json<RegisterRequest>) -> impl Respond
     )
 )]
 #[get("/user/email/all")]
-pub async fn get_all_emails() -> impl Responder {
-    let emails = sql_operations::get_all_user_emails().await;
-    HttpResponse::Ok().json(emails)
+pub async fn get_all_emails() -> Result<HttpResponse, AppError> {
+    let emails = sql_operations::get_all_user_emails().await?;
+    Ok(HttpResponse::Ok().json(emails))
 }
 
 /// Update an existing user.
@@ -40,68 +39,85 @@ pub async fn get_all_emails() -> impl Responder {
     request_body = UserToUpdate,
     responses(
         (status = 200, description = "User updated successfully."),
+        (status = 400, description = "Path ID does not match the request body."),
+        (status = 403, description = "Users can only update their own account."),
+        (status = 404, description = "User not found."),
         (status = 500, description = "Internal server error occurred.")
     )
 )]
 #[put("/update/{id}")]
-pub async fn update_existing_user(data: web::Json<UserToUpdate>) -> impl Responder {
-    match sql_operations::update_user(data.into_inner()).await {
-        true => HttpResponse::Ok().json("User updated successfully."),
-        false => {
-            error!("Failed to update user");
-            HttpResponse::InternalServerError().finish()
-        }
+pub async fn update_existing_user(
+    user: AuthenticatedUser,
+    path: web::Path<u32>,
+    data: web::Json<UserToUpdate>,
+) -> Result<HttpResponse, AppError> {
+    if path.into_inner() != data.id {
+        return Err(AppError::Validation(
+            "User ID in path does not match the request body.".to_string(),
+        ));
     }
+    user.ensure_owner(data.id)?;
+
+    sql_operations::update_user(data.into_inner()).await?;
+    Ok(HttpResponse::Ok().json("User updated successfully."))
 }
 
 /// Delete an existing user by ID.
 #[utoipa::path(
     responses(
         (status = 200, description = "User deleted successfully."),
+        (status = 403, description = "Users can only delete their own account."),
+        (status = 404, description = "User not found."),
         (status = 500, description = "Internal server error occurred.")
     )
 )]
 #[delete("/delete/{id}")]
-pub async fn delete_existing_user(path: web::Path<u32>) -> impl Responder {
+pub async fn delete_existing_user(
+    user: AuthenticatedUser,
+    path: web::Path<u32>,
+) -> Result<HttpResponse, AppError> {
     let user_id = path.into_inner();
-    match sql_operations::delete_user(user_id).await {
-        true => HttpResponse::Ok().json("User deleted successfully."),
-        false => {
-            error!("Failed to delete user with ID: {}", user_id);
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    user.ensure_owner(user_id)?;
+
+    sql_operations::delete_user(user_id).await?;
+    Ok(HttpResponse::Ok().json("User deleted successfully."))
 }
 
 /// Retrieve a user's name by ID.
 #[utoipa::path(
     responses(
         (status = 200, description = "User name retrieved successfully.", body = String),
+        (status = 404, description = "User not found."),
         (status = 500, description = "Internal server error occurred.")
     )
 )]
 #[get("/user/name/{id}")]
-pub async fn get_user_name_by_id(path: web::Path<u32>) -> impl Responder {
+pub async fn get_user_name_by_id(path: web::Path<u32>) -> Result<HttpResponse, AppError> {
     let user_id = path.into_inner();
-    let user_name = sql_operations::get_user_name(user_id).await;
-    HttpResponse::Ok().json(user_name)
+    let user_name = sql_operations::get_user_name(user_id).await?;
+    Ok(HttpResponse::Ok().json(user_name))
 }
 
-/// Update a user's password.
+/// Update the caller's password and log out all of their sessions. A forgotten password is
+/// reset through the auth service's `/password/reset/request` and `/password/reset`.
 #[utoipa::path(
     request_body = PasswordToUpdate,
     responses(
-        (status = 200, description = "Password updated successfully."),
+        (status = 200, description = "Password updated successfully, all sessions logged out."),
+        (status = 403, description = "Users can only update their own password."),
+        (status = 404, description = "User not found."),
         (status = 500, description = "Internal server error occurred.")
     )
 )]
 #[put("/password/update")]
-pub async fn update_user_password(data: web::Json<PasswordToUpdate>) -> impl Responder {
-    match sql_operations::update_password(data.into_inner()).await {
-        true => HttpResponse::Ok().json("Password updated successfully."),
-        false => {
-            error!("Failed to update password");
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+pub async fn update_user_password(
+    user: AuthenticatedUser,
+    data: web::Json<PasswordToUpdate>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = sql_operations::get_user_id_by_email(data.email.clone()).await?;
+    user.ensure_owner(user_id)?;
+
+    sql_operations::update_password(data.into_inner()).await?;
+    session::revoke_all_sessions(user_id).await?;
+    Ok(HttpResponse::Ok().json("Password updated successfully."))
 }
@@ -11,6 +11,8 @@ use utoipa_swagger_ui::SwaggerUi;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    data_access::get_pool().expect("Failed to create database pool");
+
     HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
@@ -39,16 +41,18 @@ async fn main() -> std::io::Result<()> {
         let openapi = ApiDoc::openapi();
 
         App::new()
+            .app_data(data_access::error::json_config())
+            .app_data(data_access::error::path_config())
             .wrap(cors)
             .service(
                 SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", openapi.clone()),
             )
             .service(controller::get_all_emails)
             .service(controller::register_new_user)
-            .service(controller::update_user_password)
             .service(
                 actix_web::web::scope("")
                     .wrap(HttpAuthentication::bearer(validate_jwt))
+                    .service(controller::update_user_password)
                     .service(controller::update_existing_user)
                     .service(controller::delete_existing_user)
                     .service(controller::get_user_name_by_id),
@@ -1,57 +1,63 @@
 use crate::user::{PasswordToUpdate, RegisterRequest, UserName, UserToUpdate};
+use auth::password::hash_password;
+use data_access::{AppError, AppResult};
 use mysql::{from_row, params, prelude::Queryable, Row};
 
-pub async fn register_user(request: RegisterRequest) -> bool {
+pub async fn register_user(request: RegisterRequest) -> AppResult<()> {
     let user_type_id = if request.email.ends_with("@estudiantes.uv.mx") {
         2
     } else if request.email.ends_with("@uv.mx") {
         1
     } else {
-        return false;
+        return Err(AppError::Validation(
+            "Only @uv.mx and @estudiantes.uv.mx emails can register.".to_string(),
+        ));
     };
 
-    let mut conn = data_access::get_connection();
+    let password = hash_password(request.password)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to hash password: {}", e)))?;
 
     let query = "INSERT INTO users (user_type_id, name, last_name, email, password) VALUES (:user_type_id, :name, :last_name, :email, :password)";
 
-    let result = conn
-        .exec_iter(
+    data_access::with_connection(move |conn| {
+        conn.exec_drop(
             query,
             params! {
             "user_type_id" => user_type_id,
             "name" => request.name,
             "last_name" => request.last_name,
             "email" => request.email,
-            "password" => request.password,
+            "password" => password,
             },
         )
-        .expect("Failed to execute register query")
-        .affected_rows();
-
-    result == 1
+    })
+    .await
+    .map_err(|e| match AppError::from(e) {
+        AppError::Conflict(_) => AppError::Conflict("Email is already registered.".to_string()),
+        e => e,
+    })
 }
 
-pub async fn get_all_user_emails() -> Vec<String> {
-    let mut conn = data_access::get_connection();
+pub async fn get_all_user_emails() -> AppResult<Vec<String>> {
     let query = "SELECT email FROM users";
-    let mut emails: Vec<String> = Vec::new();
 
-    conn.query_map(query, |mut row: Row| {
-        let email: String = row.take("email").unwrap();
-        emails.push(email);
+    let emails = data_access::with_connection(move |conn| {
+        conn.query_map(query, |mut row: Row| {
+            let email: String = row.take("email").unwrap();
+            email
+        })
     })
-    .expect("failed to get user emails");
+    .await?;
 
-    emails
+    Ok(emails)
 }
 
-pub async fn update_user(request: UserToUpdate) -> bool {
-    let mut conn = data_access::get_connection();
-
+pub async fn update_user(request: UserToUpdate) -> AppResult<()> {
     let query = "UPDATE users SET name = :name, last_name = :last_name WHERE user_id = :user_id";
 
-    let result = conn
-        .exec_iter(
+    let result = data_access::with_connection(move |conn| {
+        conn.exec_iter(
             query,
             params! {
             "user_id" => request.id,
@@ -59,82 +65,104 @@ pub async fn update_user(request: UserToUpdate) -> bool {
             "last_name" => request.last_name,
             },
         )
-        .expect("Failed to execute register query")
-        .affected_rows();
+        .map(|result| result.affected_rows())
+    })
+    .await?;
 
-    result == 1
-}
+    if result == 0 {
+        return Err(AppError::NotFound("User not found.".to_string()));
+    }
 
-pub async fn delete_user(id: u32) -> bool {
-    let mut conn = data_access::get_connection();
+    Ok(())
+}
 
+pub async fn delete_user(id: u32) -> AppResult<()> {
     let query = "DELETE FROM users WHERE user_id = :user_id";
 
-    let result = conn
-        .exec_iter(
+    let result = data_access::with_connection(move |conn| {
+        conn.exec_iter(
             query,
             params! {
             "user_id" => id
             },
         )
-        .expect("Failed to execute register query")
-        .affected_rows();
+        .map(|result| result.affected_rows())
+    })
+    .await?;
 
-    result == 1
-}
+    if result == 0 {
+        return Err(AppError::NotFound("User not found.".to_string()));
+    }
 
-pub async fn get_user_name(user_id: u32) -> UserName {
-    let mut conn = data_access::get_connection();
+    Ok(())
+}
 
+pub async fn get_user_name(user_id: u32) -> AppResult<UserName> {
     let query = "SELECT name, last_name FROM users WHERE user_id = :user_id";
 
-    let mut result = conn
-        .exec_iter(
+    let row: Option<Row> = data_access::with_connection(move |conn| {
+        conn.exec_first(
             query,
             params! {
                 "user_id" => user_id
             },
         )
-        .expect("Failed to execute query");
-
-    if let Some(row) = result.next() {
-        let (name, last_name): (String, String) =
-            from_row::<(String, String)>(row.expect("Row error"));
-        return UserName { name, last_name };
+    })
+    .await?;
+
+    match row {
+        Some(row) => {
+            let (name, last_name): (String, String) = from_row::<(String, String)>(row);
+            Ok(UserName { name, last_name })
+        }
+        None => Err(AppError::NotFound("User not found.".to_string())),
     }
+}
 
-    UserName {
-        name: "Unknown".to_string(),
-        last_name: "User".to_string(),
-    }
+pub async fn get_user_id_by_email(email: String) -> AppResult<u32> {
+    let query = "SELECT user_id FROM users WHERE email = :email";
+
+    let user_id: Option<u32> = data_access::with_connection(move |conn| {
+        conn.exec_first(query, params! { "email" => email })
+    })
+    .await?;
+
+    user_id.ok_or_else(|| AppError::NotFound("User not found.".to_string()))
 }
 
-pub async fn update_password(request: PasswordToUpdate) -> bool {
-    let mut conn = data_access::get_connection();
+pub async fn update_password(request: PasswordToUpdate) -> AppResult<()> {
+    let password = hash_password(request.password)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to hash password: {}", e)))?;
 
     let query = "UPDATE users SET password = :password WHERE email = :email";
 
-    let result = conn
-        .exec_iter(
+    let result = data_access::with_connection(move |conn| {
+        conn.exec_iter(
             query,
             params! {
             "email" => request.email,
-            "password" => request.password
+            "password" => password
             },
         )
-        .expect("Failed to execute register query")
-        .affected_rows();
+        .map(|result| result.affected_rows())
+    })
+    .await?;
+
+    if result == 0 {
+        return Err(AppError::NotFound("User not found.".to_string()));
+    }
 
-    result == 1
+    Ok(())
 }
 
 //only for tests
 pub async fn _get_last_user_id() -> u32 {
-    let mut conn = data_access::get_connection();
-
     let query = "SELECT MAX(user_id) FROM users";
 
-    let result: Option<u32> = conn.query_first(query).expect("Failed to execute query");
+    let result: Option<u32> = data_access::with_connection(move |conn| conn.query_first(query))
+        .await
+        .expect("Failed to execute query");
 
     result.unwrap()
 }
@@ -152,12 +180,12 @@ mod tests {
             password: "test".to_string(),
         };
         let result = register_user(user_to_insert).await;
-        assert!(result);
+        assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn test_get_all_user_emails() {
-        let result = get_all_user_emails().await;
+        let result = get_all_user_emails().await.unwrap();
         assert!(!result.is_empty());
     }
 
@@ -169,12 +197,20 @@ mod tests {
         };
 
         let result = update_password(password_to_update).await;
-        assert!(result);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_get_user_id_by_email() {
+        assert!(get_user_id_by_email("test@uv.mx".to_string()).await.is_ok());
+
+        let result = get_user_id_by_email("nobody@uv.mx".to_string()).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
     }
 
     #[tokio::test]
     async fn test_get_user_name() {
-        let result = get_user_name(1).await;
+        let result = get_user_name(1).await.unwrap();
         assert!(!result.name.is_empty() && !result.last_name.is_empty());
     }
 
@@ -187,13 +223,13 @@ mod tests {
         };
 
         let result = update_user(user_to_update).await;
-        assert!(result);
+        assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn test_delete_user() {
         let result = delete_user(_get_last_user_id().await).await;
-        assert!(result);
+        assert!(result.is_ok());
     }
 
     #[tokio::test]
@@ -205,12 +241,12 @@ mod tests {
             password: "test".to_string(),
         };
         let result = register_user(user_to_insert).await;
-        assert!(result);
+        assert!(matches!(result, Err(AppError::Validation(_))));
     }
 
     #[tokio::test]
     async fn test_get_all_user_emails_invalid() {
-        let result = get_all_user_emails().await;
+        let result = get_all_user_emails().await.unwrap();
         assert!(result.is_empty());
     }
 
@@ -222,13 +258,13 @@ mod tests {
         };
 
         let result = update_password(password_to_update).await;
-        assert!(result);
+        assert!(matches!(result, Err(AppError::NotFound(_))));
     }
 
     #[tokio::test]
     async fn test_get_user_name_invalid() {
         let result = get_user_name(0).await;
-        assert!(!result.name.is_empty() && !result.last_name.is_empty());
+        assert!(matches!(result, Err(AppError::NotFound(_))));
     }
 
     #[tokio::test]
@@ -240,12 +276,12 @@ mod tests {
         };
 
         let result = update_user(user_to_update).await;
-        assert!(result);
+        assert!(matches!(result, Err(AppError::NotFound(_))));
     }
 
     #[tokio::test]
     async fn test_delete_user_invalid() {
         let result = delete_user(0).await;
-        assert!(result);
+        assert!(matches!(result, Err(AppError::NotFound(_))));
     }
 }